@@ -4,13 +4,21 @@
 use std::{
     cell::LazyCell,
     fs,
+    io::{self, Write},
     mem::swap,
-    path::{PathBuf, MAIN_SEPARATOR, MAIN_SEPARATOR_STR},
+    num::NonZeroUsize,
+    path::{Path, PathBuf, MAIN_SEPARATOR, MAIN_SEPARATOR_STR},
+    sync::{mpsc, Arc, OnceLock},
+    thread,
+    time::Duration,
 };
 
-use clap::{ArgAction, Parser, ValueHint};
+use clap::{ArgAction, Parser, ValueEnum, ValueHint};
 use error_stack::Report;
-use fuc_engine::{CopyOp, Error};
+use fuc_engine::{
+    Atomic, Backup, CancellationToken, CopyOp, Error, Filter, FollowSymlinks, Preserve, Progress,
+    ProgressCounter, Reflink, Sparse, SpecialFiles, Update,
+};
 
 /// A zippy alternative to `cp`, a tool to copy files and directories
 #[derive(Parser, Debug)]
@@ -20,6 +28,7 @@ use fuc_engine::{CopyOp, Error};
 #[command(arg_required_else_help = true)]
 #[command(max_term_width = 100)]
 #[cfg_attr(test, command(help_expected = true))]
+#[allow(clippy::struct_excessive_bools)]
 struct Cpz {
     /// The file(s) or directory(ies) to be copied
     ///
@@ -40,6 +49,107 @@ struct Cpz {
     #[arg(short, long, default_value_t = false)]
     force: bool,
 
+    /// Only overwrite files that are out of date, merging into existing directories
+    ///
+    /// Without a value, files are only overwritten by newer ones.
+    #[arg(long, value_name = "WHEN", value_enum)]
+    #[arg(num_args = 0..=1, require_equals = true, default_missing_value = "older")]
+    update: Option<UpdateMode>,
+
+    /// Keep the destination files being overwritten under a different name
+    ///
+    /// Without a value, backups are named with `--suffix`.
+    #[arg(short, long, value_name = "CONTROL", value_enum)]
+    #[arg(num_args = 0..=1, require_equals = true, default_missing_value = "simple")]
+    backup: Option<BackupMode>,
+
+    /// The suffix appended to the names of simple backups
+    #[arg(short = 'S', long, default_value = "~")]
+    suffix: String,
+
+    /// Preserve the specified attributes
+    ///
+    /// Without a list, the mode, ownership, and timestamps are preserved.
+    #[arg(long, value_name = "ATTRS", value_enum, value_delimiter = ',')]
+    #[arg(num_args = 0.., require_equals = true)]
+    #[arg(default_missing_values = ["mode", "ownership", "timestamps"])]
+    preserve: Vec<PreserveAttr>,
+
+    /// Control whether files are cloned on copy-on-write filesystems
+    ///
+    /// Without a value, files are always cloned.
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = ReflinkMode::Auto)]
+    #[arg(num_args = 0..=1, require_equals = true, default_missing_value = "always")]
+    reflink: ReflinkMode,
+
+    /// Control whether holes are reproduced in the copies
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = SparseMode::Auto)]
+    #[arg(require_equals = true)]
+    sparse: SparseMode,
+
+    /// Control what happens to FIFOs, sockets, and device nodes
+    #[arg(long, value_name = "ACTION", value_enum)]
+    #[arg(default_value_t = SpecialFilesMode::Recreate)]
+    special_files: SpecialFilesMode,
+
+    /// Copy the targets of symbolic links instead of the links themselves
+    #[arg(short = 'L', long, default_value_t = false)]
+    #[arg(overrides_with = "dereference_args")]
+    dereference: bool,
+
+    /// Only follow symbolic links passed in as sources
+    #[arg(short = 'H', long, default_value_t = false)]
+    #[arg(overrides_with = "dereference")]
+    dereference_args: bool,
+
+    /// Don't copy the contents of directories on other filesystems
+    #[arg(short = 'x', long, default_value_t = false)]
+    one_file_system: bool,
+
+    /// Never let the destination be seen half-copied
+    ///
+    /// Without a value, each file is written under a temporary name and then renamed into place.
+    #[arg(long, value_name = "WHAT", value_enum)]
+    #[arg(num_args = 0..=1, require_equals = true, default_missing_value = "files")]
+    atomic: Option<AtomicMode>,
+
+    /// Remove whatever is in the destination directories but not in the source
+    ///
+    /// Excluded files and backups of copied files are kept. Dry runs list the files that would be
+    /// removed along with those that would be copied.
+    #[arg(long, default_value_t = false)]
+    delete: bool,
+
+    /// Don't copy files matching this glob
+    ///
+    /// Patterns are relative to the directory being copied. Patterns without a `/` match file
+    /// names at any depth, a trailing `/` only matches directories, and excluded directories
+    /// aren't descended into.
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Copy files matching this glob even if they were excluded
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// Show a live count of the files copied so far on stderr
+    #[arg(long, default_value_t = false)]
+    progress: bool,
+
+    /// List what would be copied without copying anything
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// Keep copying after a failure and report every failure at the end
+    #[arg(long, default_value_t = false)]
+    continue_on_error: bool,
+
+    /// The maximum number of threads to copy with
+    ///
+    /// Defaults to the number of available CPUs. Use `1` to copy files one at a time.
+    #[arg(short = 'j', long, value_name = "N", env = "FUC_THREADS")]
+    threads: Option<NonZeroUsize>,
+
     /// Reverse the argument order so that it becomes `cpz <TO> <FROM>...`
     #[arg(short = 't', long, default_value_t = false)]
     reverse_args: bool,
@@ -50,6 +160,130 @@ struct Cpz {
     help: Option<bool>,
 }
 
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum UpdateMode {
+    /// Overwrite files last modified before their source
+    Older,
+    /// Overwrite files whose size or modification time differs from their source
+    Differ,
+}
+
+impl From<UpdateMode> for Update {
+    fn from(mode: UpdateMode) -> Self {
+        match mode {
+            UpdateMode::Older => Self::Older,
+            UpdateMode::Differ => Self::Differ,
+        }
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum BackupMode {
+    /// Append the suffix, replacing any previous backup
+    Simple,
+    /// Append `.~N~` numbered after the highest existing one
+    Numbered,
+}
+
+impl From<BackupMode> for Backup {
+    fn from(mode: BackupMode) -> Self {
+        match mode {
+            BackupMode::Simple => Self::Simple,
+            BackupMode::Numbered => Self::Numbered,
+        }
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum PreserveAttr {
+    /// Permission bits, including the setuid, setgid, and sticky bits
+    Mode,
+    /// Owning user and group
+    Ownership,
+    /// Access and modification times
+    Timestamps,
+    /// Extended attributes, including ACLs and security labels
+    Xattr,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum ReflinkMode {
+    /// Clone files when possible and fall back to copying their contents
+    Auto,
+    /// Fail if a file can't be cloned
+    Always,
+    /// Copy file contents without asking for clones
+    Never,
+}
+
+impl From<ReflinkMode> for Reflink {
+    fn from(mode: ReflinkMode) -> Self {
+        match mode {
+            ReflinkMode::Auto => Self::Auto,
+            ReflinkMode::Always => Self::Always,
+            ReflinkMode::Never => Self::Never,
+        }
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum SparseMode {
+    /// Reproduce the holes of files that take up less space than their size
+    Auto,
+    /// Reproduce holes and turn blocks of zeros into holes
+    Always,
+    /// Fill holes with zeros
+    Never,
+}
+
+impl From<SparseMode> for Sparse {
+    fn from(mode: SparseMode) -> Self {
+        match mode {
+            SparseMode::Auto => Self::Auto,
+            SparseMode::Always => Self::Always,
+            SparseMode::Never => Self::Never,
+        }
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum AtomicMode {
+    /// Rename each finished file over its destination
+    Files,
+    /// Build directories under a temporary name and swap them with their destination, replacing
+    /// rather than merging into it
+    Trees,
+}
+
+impl From<AtomicMode> for Atomic {
+    fn from(mode: AtomicMode) -> Self {
+        match mode {
+            AtomicMode::Files => Self::Files,
+            AtomicMode::Trees => Self::Trees,
+        }
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum SpecialFilesMode {
+    /// Create new nodes with the same type, mode, and device number
+    Recreate,
+    /// Leave them out of the copy
+    Skip,
+    /// Fail the copy
+    Error,
+}
+
+impl From<SpecialFilesMode> for SpecialFiles {
+    fn from(mode: SpecialFilesMode) -> Self {
+        match mode {
+            SpecialFilesMode::Recreate => Self::Recreate,
+            SpecialFilesMode::Skip => Self::Skip,
+            SpecialFilesMode::Error => Self::Fail,
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 enum CliError {
     #[error("{0}")]
@@ -96,30 +330,56 @@ fn main() -> error_stack::Result<(), CliError> {
     };
 
     let args = Cpz::parse();
+    let cancel = cancel_on_interrupt();
 
-    copy(args).map_err(|e| {
-        let wrapper = CliError::Wrapper(format!("{e}"));
-        match e {
-            Error::Io { error, context } => Report::from(error)
-                .attach_printable(context)
-                .change_context(wrapper),
-            Error::AlreadyExists { file } => {
-                let report = Report::from(wrapper);
-                match file.symlink_metadata().map(|m| m.is_dir()) {
-                    Ok(true) => {
-                        let mut file = file.into_os_string();
-                        file.push(MAIN_SEPARATOR_STR);
-                        report.attach_printable(format!(
-                            "Use the path {file:?} to copy into the directory."
-                        ))
-                    }
-                    Ok(false) | Err(_) => report.attach_printable("Use --force to overwrite."),
+    with_progress(args.progress, |progress| {
+        with_dry_run(args.dry_run, progress, |progress| {
+            copy(args, progress, cancel)
+        })
+    })
+    .map_err(into_report)
+}
+
+fn into_report(e: Error) -> Report<CliError> {
+    let wrapper = CliError::Wrapper(format!("{e}"));
+    match e {
+        Error::Aggregate { errors } => {
+            let mut reports = errors.into_iter().map(into_report);
+            let Some(mut report) = reports.next() else {
+                return Report::from(wrapper);
+            };
+            for next in reports {
+                report.extend_one(next);
+            }
+            report.change_context(wrapper)
+        }
+        Error::Io { error, context } => Report::from(error)
+            .attach_printable(context)
+            .change_context(wrapper),
+        Error::AlreadyExists { file } => {
+            let report = Report::from(wrapper);
+            match file.symlink_metadata().map(|m| m.is_dir()) {
+                Ok(true) => {
+                    let mut file = file.into_os_string();
+                    file.push(MAIN_SEPARATOR_STR);
+                    report.attach_printable(format!(
+                        "Use the path {file:?} to copy into the directory."
+                    ))
                 }
+                Ok(false) | Err(_) => report.attach_printable("Use --force to overwrite."),
             }
-            Error::Join | Error::BadPath | Error::Internal => Report::from(wrapper),
-            Error::PreserveRoot | Error::NotFound { file: _ } => unreachable!(),
         }
-    })
+        Error::SpecialFile { file: _ } => {
+            Report::from(wrapper).attach_printable("Use --special-files=skip to leave it out.")
+        }
+        Error::InvalidPattern { pattern: _ }
+        | Error::SymlinkLoop { file: _ }
+        | Error::Join
+        | Error::BadPath
+        | Error::Cancelled
+        | Error::Internal => Report::from(wrapper),
+        Error::PreserveRoot | Error::NotFound { file: _ } => unreachable!(),
+    }
 }
 
 fn copy(
@@ -127,15 +387,59 @@ fn copy(
         mut from,
         mut to,
         force,
+        update,
+        backup,
+        suffix,
+        preserve,
+        reflink,
+        sparse,
+        special_files,
+        dereference,
+        dereference_args,
+        one_file_system,
+        atomic,
+        delete,
+        exclude,
+        include,
+        progress: _,
+        dry_run,
+        continue_on_error,
+        threads,
         reverse_args,
         help: _,
     }: Cpz,
+    progress: Arc<dyn Progress>,
+    cancel: CancellationToken,
 ) -> Result<(), Error> {
     if reverse_args {
         swap(&mut to, &mut from[0]);
     }
     let from = from;
     let to = to;
+    let preserve = preserve
+        .into_iter()
+        .fold(Preserve::default(), |mut preserve, attr| {
+            match attr {
+                PreserveAttr::Mode => preserve.mode = true,
+                PreserveAttr::Ownership => preserve.ownership = true,
+                PreserveAttr::Timestamps => preserve.timestamps = true,
+                PreserveAttr::Xattr => preserve.xattr = true,
+            }
+            preserve
+        });
+    let follow_symlinks = if dereference {
+        FollowSymlinks::Always
+    } else if dereference_args {
+        FollowSymlinks::CommandLine
+    } else {
+        FollowSymlinks::Never
+    };
+    let filter = exclude
+        .iter()
+        .try_fold(Filter::default(), |filter, pattern| filter.exclude(pattern))?;
+    let filter = include
+        .iter()
+        .try_fold(filter, |filter, pattern| filter.include(pattern))?;
 
     #[allow(clippy::unnested_or_patterns)]
     let is_into_directory = LazyCell::new(|| {
@@ -152,43 +456,183 @@ fn copy(
                 | (Some('.'), Some('.'), Some(MAIN_SEPARATOR)) // */..
         )
     });
-    if from.len() > 1 || *is_into_directory {
+    if !dry_run && (from.len() > 1 || *is_into_directory) {
         fs::create_dir_all(&to).map_err(|error| Error::Io {
             error,
             context: format!("Failed to create directory {to:?}").into(),
         })?;
     }
 
-    if from.len() > 1 {
-        CopyOp::builder()
-            .files(from.into_iter().map(|path| {
+    let files = if from.len() > 1 {
+        from.into_iter()
+            .map(|path| {
                 let to = path
                     .file_name()
                     .map_or_else(|| to.clone(), |name| to.join(name));
                 (path, to)
-            }))
-            .force(force)
-            .build()
-            .run()
+            })
+            .collect()
     } else {
-        CopyOp::builder()
-            .files([{
-                let from = from.into_iter().next().unwrap();
-                let to = {
-                    let is_into_directory = *is_into_directory;
-                    let mut to = to;
-                    if is_into_directory && let Some(name) = from.file_name() {
-                        to.push(name);
-                    }
-                    to
-                };
-
-                (from, to)
-            }])
-            .force(force)
-            .build()
-            .run()
+        let from = from.into_iter().next().unwrap();
+        let to = {
+            let is_into_directory = *is_into_directory;
+            let mut to = to;
+            if is_into_directory && let Some(name) = from.file_name() {
+                to.push(name);
+            }
+            to
+        };
+
+        vec![(from, to)]
+    };
+
+    CopyOp::builder()
+        .files(files)
+        .force(force)
+        .update(update.map_or(Update::Always, Update::from))
+        .backup(backup.map_or(Backup::Never, Backup::from))
+        .backup_suffix(suffix)
+        .preserve(preserve)
+        .reflink(reflink.into())
+        .sparse(sparse.into())
+        .special_files(special_files.into())
+        .follow_symlinks(follow_symlinks)
+        .one_file_system(one_file_system)
+        .atomic(atomic.map_or(Atomic::Never, Atomic::from))
+        .mirror(delete)
+        .filter(filter)
+        .progress(progress)
+        .dry_run(dry_run)
+        .continue_on_error(continue_on_error)
+        .threads(threads)
+        .cancel(cancel)
+        .build()
+        .run()
+}
+
+fn with_progress<T>(enabled: bool, f: impl FnOnce(Arc<dyn Progress>) -> T) -> T {
+    if !enabled {
+        return f(Arc::new(()));
+    }
+
+    let counter = Arc::new(ProgressCounter::default());
+    let (done, wait) = mpsc::channel::<()>();
+    let render = thread::spawn({
+        let counter = counter.clone();
+        move || {
+            let print = |end| {
+                let _ = write!(
+                    io::stderr(),
+                    "\r\x1b[KCopied {} files and {} directories ({}){end}",
+                    counter.files(),
+                    counter.dirs(),
+                    HumanBytes(counter.bytes()),
+                );
+            };
+            while wait.recv_timeout(Duration::from_millis(100))
+                == Err(mpsc::RecvTimeoutError::Timeout)
+            {
+                print("");
+            }
+            print("\n");
+        }
+    });
+
+    let result = f(counter);
+    drop(done);
+    let _ = render.join();
+    result
+}
+
+struct HumanBytes(u64);
+
+impl std::fmt::Display for HumanBytes {
+    #[allow(clippy::cast_precision_loss)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+
+        let Self(bytes) = *self;
+        let mut size = bytes as f64;
+        let mut unit = 0;
+        while size >= 1024. && unit < UNITS.len() - 1 {
+            size /= 1024.;
+            unit += 1;
+        }
+        if unit == 0 {
+            write!(f, "{bytes} {}", UNITS[unit])
+        } else {
+            write!(f, "{size:.1} {}", UNITS[unit])
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DryRun {
+    progress: Arc<dyn Progress>,
+    counter: ProgressCounter,
+}
+
+impl Progress for DryRun {
+    fn file_done(&self, bytes: u64) {
+        self.counter.file_done(bytes);
+        self.progress.file_done(bytes);
+    }
+
+    fn dir_done(&self) {
+        self.counter.dir_done();
+        self.progress.dir_done();
+    }
+
+    fn planned(&self, path: &Path) {
+        let _ = writeln!(io::stdout(), "{}", path.display());
+    }
+}
+
+fn with_dry_run<T>(
+    enabled: bool,
+    progress: Arc<dyn Progress>,
+    f: impl FnOnce(Arc<dyn Progress>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    if !enabled {
+        return f(progress);
+    }
+
+    let dry_run = Arc::new(DryRun {
+        progress,
+        counter: ProgressCounter::default(),
+    });
+    let result = f(dry_run.clone())?;
+    let _ = writeln!(
+        io::stdout(),
+        "Would copy {} files and {} directories",
+        dry_run.counter.files(),
+        dry_run.counter.dirs(),
+    );
+    Ok(result)
+}
+
+/// Turns the first Ctrl-C into a clean stop between directory entries. The
+/// handler resets itself, so a second Ctrl-C kills the process as usual.
+fn cancel_on_interrupt() -> CancellationToken {
+    static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+    extern "C" fn handle(_: libc::c_int) {
+        if let Some(token) = TOKEN.get() {
+            token.cancel();
+        }
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+
+    let token = TOKEN.get_or_init(CancellationToken::default).clone();
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
     }
+    token
 }
 
 #[cfg(test)]
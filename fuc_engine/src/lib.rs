@@ -8,7 +8,12 @@ use std::{borrow::Cow, io, path::PathBuf};
 
 use thiserror::Error;
 
-pub use crate::ops::{copy_file, remove_file, remove_file as remove_dir_all, CopyOp, RemoveOp};
+pub use crate::ops::{
+    copy_file, move_file, remove_file, remove_file as remove_dir_all, Atomic, Backup,
+    CancellationToken, Confirm, CopyOp, FileKind, Filter, FollowSymlinks, MoveOp, Predicate,
+    Preserve, Progress, ProgressCounter, Purge, Reflink, RemoveOp, Sparse, SpecialFiles, Timestamp,
+    Trash, UnsupportedXattrs, Update,
+};
 
 mod ops;
 
@@ -27,8 +32,18 @@ pub enum Error {
     BadPath,
     #[error("File or directory already exists: {file:?}")]
     AlreadyExists { file: PathBuf },
+    #[error("Invalid glob pattern: {pattern:?}")]
+    InvalidPattern { pattern: String },
     #[error("File or directory not found: {file:?}")]
     NotFound { file: PathBuf },
+    #[error("Refusing to copy special file: {file:?}")]
+    SpecialFile { file: PathBuf },
+    #[error("Symbolic link loop detected: {file:?}")]
+    SymlinkLoop { file: PathBuf },
+    #[error("{} operations failed", errors.len())]
+    Aggregate { errors: Vec<Self> },
+    #[error("The operation was cancelled")]
+    Cancelled,
     #[error("An internal bug occurred, please report this")]
     Internal,
 }
@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+
+use crate::{ops::IoErr, Error};
+
+/// The hidden directory files wait in until they're deleted in the
+/// background. Whatever an interrupted purge leaves in one is deleted by the
+/// next background removal of anything in the same directory.
+pub const STAGING_DIR: &str = ".fuc-staging";
+
+/// Moves a file into the staging directory inside `dir`, returning that
+/// staging directory or `None` if the file can't be moved there.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "trace"))]
+pub fn stage(file: &Path, dir: &Path) -> Result<Option<PathBuf>, Error> {
+    let staging = dir.join(STAGING_DIR);
+    let staged = compat::stage(file, &staging)
+        .map_io_err(|| format!("Failed to move file into staging directory: {file:?}"))?;
+    Ok(staged.then_some(staging))
+}
+
+#[cfg(target_os = "linux")]
+mod compat {
+    use std::{
+        fs::DirBuilder,
+        io,
+        os::unix::fs::DirBuilderExt,
+        path::Path,
+        process,
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    use rustix::{
+        fs::{renameat_with, RenameFlags, CWD},
+        io::Errno,
+    };
+
+    pub fn stage(file: &Path, staging: &Path) -> io::Result<bool> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        loop {
+            match DirBuilder::new().mode(0o700).create(staging) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                r => r?,
+            }
+            let name = format!(
+                "{}-{}",
+                process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            match renameat_with(CWD, file, CWD, staging.join(name), RenameFlags::NOREPLACE) {
+                Ok(()) => return Ok(true),
+                // A staged file from a previous run has the same name, or another run
+                // just finished deleting the staging directory.
+                Err(Errno::EXIST | Errno::NOENT) if file.symlink_metadata().is_ok() => {}
+                // Mount points and the staging directory itself can't be moved into it.
+                Err(Errno::XDEV | Errno::BUSY | Errno::INVAL) => return Ok(false),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod compat {
+    use std::{io, path::Path};
+
+    pub fn stage(_: &Path, _: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+}
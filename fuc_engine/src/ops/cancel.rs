@@ -0,0 +1,36 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::Error;
+
+/// Stops an in-flight operation from another thread.
+///
+/// Clones share the same flag, so hand one to the operation and keep another
+/// around to call [`CancellationToken::cancel`] on. Workers check the token
+/// between directory entries: anything already copied or removed stays that
+/// way, and the operation fails with [`Error::Cancelled`].
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Asks the operations holding this token to stop. Only touches an
+    /// atomic, so it may be called from a signal handler.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
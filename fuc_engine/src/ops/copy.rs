@@ -1,10 +1,24 @@
-use std::{borrow::Cow, fmt::Debug, fs, io, marker::PhantomData, path::Path};
+use std::{
+    borrow::Cow,
+    fmt::Debug,
+    fs, io,
+    marker::PhantomData,
+    mem,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
+use glob::{MatchOptions, Pattern};
 use typed_builder::TypedBuilder;
 
 use crate::{
-    ops::{compat::DirectoryOp, IoErr},
-    Error,
+    ops::{compat::DirectoryOp, CancellationToken, Failures, IoErr, Progress},
+    Error, RemoveOp,
 };
 
 /// Copies a file or directory at this path.
@@ -19,6 +33,285 @@ pub fn copy_file<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<(), E
         .run()
 }
 
+/// File attributes to carry over from the source to the copy.
+///
+/// Only honored on Linux.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Preserve {
+    /// Apply the exact permission bits instead of masking them with the umask.
+    pub mode: bool,
+    /// Apply the owning user and group, falling back to only the group for
+    /// unprivileged users.
+    pub ownership: bool,
+    /// Apply the access and modification times.
+    pub timestamps: bool,
+    /// Copy extended attributes, including POSIX ACLs, file capabilities, and
+    /// security labels.
+    pub xattr: bool,
+    /// What to do with extended attributes the destination refuses.
+    pub unsupported_xattrs: UnsupportedXattrs,
+}
+
+impl Preserve {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    const fn any(self) -> bool {
+        let Self {
+            mode,
+            ownership,
+            timestamps,
+            xattr,
+            unsupported_xattrs: _,
+        } = self;
+        mode || ownership || timestamps || xattr
+    }
+}
+
+/// How to handle extended attributes the destination filesystem doesn't
+/// support.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum UnsupportedXattrs {
+    /// Leave the attribute behind and keep going.
+    #[default]
+    Skip,
+    /// Fail the copy.
+    Fail,
+}
+
+/// Whether to share data blocks between the source and the copy on
+/// copy-on-write filesystems such as btrfs and XFS.
+///
+/// Only honored on Linux.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Reflink {
+    /// Clone files when possible and fall back to copying their contents.
+    #[default]
+    Auto,
+    /// Fail if a file can't be cloned.
+    Always,
+    /// Don't ask for clones, though the kernel may still share data blocks
+    /// when it offloads a copy.
+    Never,
+}
+
+/// Whether to leave holes in the copy instead of writing out zeros.
+///
+/// Only honored on Linux.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Sparse {
+    /// Reproduce the holes of files that take up less space than their size.
+    #[default]
+    Auto,
+    /// Reproduce holes and additionally turn blocks of zeros into holes.
+    Always,
+    /// Fill holes with zeros.
+    Never,
+}
+
+/// What to do with FIFOs, sockets, and device nodes, whose contents can't be
+/// copied.
+///
+/// Only honored on Linux.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum SpecialFiles {
+    /// Create a new node with the same type, mode, and device number.
+    ///
+    /// Creating device nodes usually requires privileges, so unprivileged
+    /// copies of them fail rather than falling back to reading the device.
+    /// Sockets are recreated like FIFOs: the new node has no listener and is
+    /// only useful as a placeholder, but it keeps the tree intact, so there is
+    /// no separate policy for them.
+    #[default]
+    Recreate,
+    /// Leave them out of the copy.
+    Skip,
+    /// Fail the copy.
+    Fail,
+}
+
+/// Which symlinks in the source to follow instead of copying them as links.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum FollowSymlinks {
+    /// Copy every symlink as a link.
+    #[default]
+    Never,
+    /// Follow symlinks passed in as sources, but not the ones found inside
+    /// them.
+    CommandLine,
+    /// Copy the targets of all symlinks.
+    ///
+    /// Only honored for the sources themselves on platforms other than Linux.
+    Always,
+}
+
+/// Which files already in the destination get overwritten.
+///
+/// Symlinks and special files are compared like regular files, without
+/// following destination symlinks, and destinations of a different type than
+/// their source are always overwritten unless nothing may be. Only honored on
+/// Linux.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Update {
+    /// Overwrite every file.
+    #[default]
+    Always,
+    /// Overwrite files whose size or modification time differs from their
+    /// source. Copies only keep matching their source when timestamps are
+    /// preserved.
+    Differ,
+    /// Overwrite files last modified before their source.
+    Older,
+    /// Leave existing files alone.
+    Never,
+}
+
+/// How to hold on to destination files that are about to be overwritten.
+///
+/// Only honored on Linux.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Backup {
+    /// Overwrite them without a trace.
+    #[default]
+    Never,
+    /// Rename them to their name plus a suffix, replacing any previous backup.
+    Simple,
+    /// Rename them to their name plus `.~N~`, one past the highest existing
+    /// `N`, starting from one.
+    Numbered,
+}
+
+/// Whether the copy may be observed half-written.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Atomic {
+    /// Write straight into the destination, truncating existing files first.
+    #[default]
+    Never,
+    /// Write each regular file under a hidden temporary name and rename it over
+    /// the destination once complete.
+    ///
+    /// Only honored on Linux.
+    Files,
+    /// Build each directory copy under a hidden temporary name and swap it with
+    /// the destination in one step, discarding the old directory instead of
+    /// merging into it. Single files are replaced as with [`Atomic::Files`].
+    Trees,
+}
+
+/// Glob patterns deciding which files make it into the copy.
+///
+/// Patterns are matched against paths relative to the directory being copied.
+/// Patterns without a `/` match names at any depth while the others must match
+/// the whole relative path, and a trailing `/` only matches directories. Files
+/// matching an exclusion are left out unless they also match an inclusion.
+/// Excluded directories are never read.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+}
+
+impl Filter {
+    /// Keep files matching this pattern, even if they are excluded.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidPattern`] if the pattern isn't a valid glob.
+    pub fn include(mut self, pattern: &str) -> Result<Self, Error> {
+        self.include.push(Glob::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Leave out files matching this pattern.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidPattern`] if the pattern isn't a valid glob.
+    pub fn exclude(mut self, pattern: &str) -> Result<Self, Error> {
+        self.exclude.push(Glob::new(pattern)?);
+        Ok(self)
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.exclude.is_empty()
+    }
+
+    fn excludes(&self, path: &Path, is_dir: bool) -> bool {
+        let matches = |glob: &Glob| glob.matches(path, is_dir);
+        self.exclude.iter().any(matches) && !self.include.iter().any(matches)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Glob {
+    pattern: Pattern,
+    whole_path: bool,
+    dirs_only: bool,
+}
+
+impl Glob {
+    fn new(glob: &str) -> Result<Self, Error> {
+        let (pattern, dirs_only) = glob
+            .strip_suffix('/')
+            .map_or((glob, false), |pattern| (pattern, true));
+        let whole_path = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+
+        Ok(Self {
+            pattern: Pattern::new(pattern).map_err(|_| Error::InvalidPattern {
+                pattern: glob.to_owned(),
+            })?,
+            whole_path,
+            dirs_only,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        const OPTIONS: MatchOptions = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+
+        if self.dirs_only && !is_dir {
+            return false;
+        }
+        if self.whole_path {
+            self.pattern.matches_path_with(path, OPTIONS)
+        } else {
+            path.file_name()
+                .is_some_and(|name| self.pattern.matches_path_with(Path::new(name), OPTIONS))
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct Options {
+    update: Update,
+    backup: Backup,
+    backup_suffix: String,
+    preserve: Preserve,
+    reflink: Reflink,
+    sparse: Sparse,
+    special_files: SpecialFiles,
+    follow_symlinks: FollowSymlinks,
+    one_file_system: bool,
+    atomic: Atomic,
+    mirror: bool,
+    filter: Arc<Filter>,
+    progress: Arc<dyn Progress>,
+    dry_run: bool,
+    failures: Failures,
+    threads: Option<NonZeroUsize>,
+    cancel: CancellationToken,
+    /// Shared by every file and directory being copied so that links between
+    /// them are kept too.
+    #[cfg(target_os = "linux")]
+    hard_links: Arc<compat::HardLinks>,
+}
+
+// The flags are independent builder options, not a state machine.
+#[allow(clippy::struct_excessive_bools)]
 #[derive(TypedBuilder, Debug)]
 pub struct CopyOp<
     'a,
@@ -30,6 +323,61 @@ pub struct CopyOp<
     files: F,
     #[builder(default = false)]
     force: bool,
+    /// Skip files that are already up to date. Existing destinations are
+    /// merged into rather than rejected unless every file is overwritten.
+    #[builder(default)]
+    update: Update,
+    #[builder(default)]
+    backup: Backup,
+    /// Appended to the names of simple backups.
+    #[builder(default = "~".to_owned())]
+    backup_suffix: String,
+    #[builder(default)]
+    preserve: Preserve,
+    #[builder(default)]
+    reflink: Reflink,
+    #[builder(default)]
+    sparse: Sparse,
+    #[builder(default)]
+    special_files: SpecialFiles,
+    #[builder(default)]
+    follow_symlinks: FollowSymlinks,
+    /// Create directories on other mounts, but leave them empty.
+    ///
+    /// Only honored on Linux.
+    #[builder(default = false)]
+    one_file_system: bool,
+    /// Keep readers from ever seeing partially copied files or directories.
+    #[builder(default)]
+    atomic: Atomic,
+    /// Remove whatever is in the destination directories but not in their
+    /// source, leaving behind an exact copy. Excluded files and backups of
+    /// copied files are kept, and dry runs plan the removals instead.
+    #[builder(default = false)]
+    mirror: bool,
+    #[builder(default)]
+    filter: Filter,
+    /// Notified of every file and directory copied, as well as those removed
+    /// when mirroring.
+    #[builder(default = Arc::new(()))]
+    progress: Arc<dyn Progress>,
+    /// Walk the source trees without writing anything, handing each path that
+    /// would have been created to [`Progress::planned`] instead.
+    #[builder(default = false)]
+    dry_run: bool,
+    /// Keep copying the remaining files after a failure, reporting every
+    /// failure at the end in an [`Error::Aggregate`].
+    #[builder(default = false)]
+    continue_on_error: bool,
+    /// The most threads that may copy at once, defaulting to the available
+    /// parallelism. A single thread copies everything in order.
+    ///
+    /// Only honored on Linux.
+    #[builder(default)]
+    threads: Option<NonZeroUsize>,
+    /// Stops the copy early with [`Error::Cancelled`] once cancelled.
+    #[builder(default)]
+    cancel: CancellationToken,
     #[builder(default)]
     _marker1: PhantomData<&'a I1>,
     #[builder(default)]
@@ -49,16 +397,40 @@ impl<
     /// # Errors
     ///
     /// Returns the underlying I/O errors that occurred.
-    pub fn run(self) -> Result<(), Error> {
-        let copy = compat::copy_impl();
-        let result = schedule_copies(self, &copy);
-        copy.finish().and(result)
+    pub fn run(mut self) -> Result<(), Error> {
+        let options = Options {
+            update: self.update,
+            backup: self.backup,
+            backup_suffix: mem::take(&mut self.backup_suffix),
+            preserve: self.preserve,
+            reflink: self.reflink,
+            sparse: self.sparse,
+            special_files: self.special_files,
+            follow_symlinks: self.follow_symlinks,
+            one_file_system: self.one_file_system,
+            atomic: self.atomic,
+            mirror: self.mirror,
+            filter: Arc::new(mem::take(&mut self.filter)),
+            progress: self.progress.clone(),
+            dry_run: self.dry_run,
+            failures: Failures::new(self.continue_on_error),
+            threads: self.threads,
+            cancel: self.cancel.clone(),
+            #[cfg(target_os = "linux")]
+            hard_links: Arc::default(),
+        };
+        let copy = compat::copy_impl(options.clone());
+        let mut trees = Vec::new();
+        let result = schedule_copies(self, &options, &copy, &mut trees);
+        let result = copy.finish().and(result);
+        let result = replace_trees(trees, result, &options);
+        options.failures.finish(result)
     }
 }
 
 #[cfg_attr(
     feature = "tracing",
-    tracing::instrument(level = "trace", skip(files, copy))
+    tracing::instrument(level = "trace", skip(files, options, copy, trees))
 )]
 fn schedule_copies<
     'a,
@@ -70,85 +442,211 @@ fn schedule_copies<
     CopyOp {
         files,
         force,
+        update: _,
+        backup: _,
+        backup_suffix: _,
+        preserve: _,
+        reflink: _,
+        sparse: _,
+        special_files: _,
+        follow_symlinks: _,
+        one_file_system: _,
+        atomic: _,
+        mirror: _,
+        filter: _,
+        progress: _,
+        dry_run: _,
+        continue_on_error: _,
+        threads: _,
+        cancel: _,
         _marker1: _,
         _marker2: _,
     }: CopyOp<'a, 'b, I1, I2, F>,
+    options: &Options,
     copy: &impl DirectoryOp<(Cow<'a, Path>, Cow<'b, Path>)>,
+    trees: &mut Vec<(PathBuf, PathBuf)>,
 ) -> Result<(), Error> {
     for (from, to) in files {
-        let from = from.into();
-        let to = to.into();
-        if !force {
-            match to.symlink_metadata() {
-                Ok(_) => {
-                    return Err(Error::AlreadyExists {
-                        file: to.into_owned(),
-                    });
-                }
-                Err(e) if e.kind() == io::ErrorKind::NotFound => {
-                    // Do nothing, this is good
-                }
-                r => {
-                    r.map_io_err(|| format!("Failed to read metadata for file: {to:?}"))?;
-                }
+        options.cancel.check()?;
+        options.failures.recover(schedule_copy(
+            from.into(),
+            to.into(),
+            force,
+            options,
+            copy,
+            trees,
+        ))?;
+    }
+    Ok(())
+}
+
+fn schedule_copy<'a, 'b>(
+    from: Cow<'a, Path>,
+    to: Cow<'b, Path>,
+    force: bool,
+    options: &Options,
+    copy: &impl DirectoryOp<(Cow<'a, Path>, Cow<'b, Path>)>,
+    trees: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<(), Error> {
+    // Updating implies overwriting, but only where the update policy is honored.
+    let force = force || (cfg!(target_os = "linux") && options.update != Update::Always);
+    if !force {
+        match to.symlink_metadata() {
+            Ok(_) => {
+                return Err(Error::AlreadyExists {
+                    file: to.into_owned(),
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // Do nothing, this is good
+            }
+            r => {
+                r.map_io_err(|| format!("Failed to read metadata for file: {to:?}"))?;
             }
         }
+    }
 
+    let (from, from_metadata) = if options.follow_symlinks == FollowSymlinks::Never {
         let from_metadata = from
             .symlink_metadata()
             .map_io_err(|| format!("Failed to read metadata for file: {from:?}"))?;
+        (from, from_metadata)
+    } else {
+        let from_metadata = from
+            .metadata()
+            .map_io_err(|| format!("Failed to read metadata for file: {from:?}"))?;
+        // Work with the target from now on so it never has to be dereferenced again.
+        let from = if from.is_symlink() {
+            Cow::Owned(
+                from.canonicalize()
+                    .map_io_err(|| format!("Failed to resolve symlink: {from:?}"))?,
+            )
+        } else {
+            from
+        };
+        (from, from_metadata)
+    };
 
-        if let Some(parent) = to.parent() {
-            fs::create_dir_all(parent)
-                .map_io_err(|| format!("Failed to create parent directory: {parent:?}"))?;
+    if options.dry_run {
+        if from_metadata.is_dir() {
+            copy.run((from, to))?;
+        } else {
+            options.progress.planned(&to);
+            options.progress.file_done(0);
         }
+        return Ok(());
+    }
+
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)
+            .map_io_err(|| format!("Failed to create parent directory: {parent:?}"))?;
+    }
 
+    if from_metadata.is_dir() {
+        #[cfg_attr(not(unix), allow(unused_mut))]
+        let mut builder = fs::DirBuilder::new();
         #[cfg(unix)]
-        if from_metadata.is_dir() {
+        {
             use std::os::unix::fs::{DirBuilderExt, MetadataExt};
-            match fs::DirBuilder::new()
-                .mode(
-                    fs::symlink_metadata(&from)
-                        .map_io_err(|| format!("Failed to stat directory: {from:?}"))?
-                        .mode(),
-                )
-                .create(&to)
-            {
-                Err(e) if force && e.kind() == io::ErrorKind::AlreadyExists => {}
-                r => r.map_io_err(|| format!("Failed to create directory: {to:?}"))?,
-            };
-            copy.run((from, to))?;
-        } else if from_metadata.is_symlink() {
-            let link =
-                fs::read_link(&from).map_io_err(|| format!("Failed to read symlink: {from:?}"))?;
-            std::os::unix::fs::symlink(link, &to)
-                .map_io_err(|| format!("Failed to create symlink: {to:?}"))?;
-        } else {
-            fs::copy(&from, &to).map_io_err(|| format!("Failed to copy file: {from:?}"))?;
+            let mode = from_metadata.mode();
+            // Keep the directory writable until its contents have been copied.
+            builder.mode(if options.preserve.mode {
+                mode | 0o700
+            } else {
+                mode
+            });
         }
 
-        #[cfg(not(unix))]
-        if from_metadata.is_dir() {
-            copy.run((from, to))?;
-        } else {
-            fs::copy(&from, &to).map_io_err(|| format!("Failed to copy file: {from:?}"))?;
+        if options.atomic == Atomic::Trees {
+            // Build the copy next to its destination so it can be renamed into place.
+            let temp = loop {
+                let temp = to.with_file_name(temp_name());
+                match builder.create(&temp) {
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                    r => break r.map(|()| temp),
+                }
+            }
+            .map_io_err(|| format!("Failed to create temporary directory for: {to:?}"))?;
+            trees.push((temp.clone(), to.into_owned()));
+            copy.run((from, Cow::Owned(temp)))?;
+            return Ok(());
         }
+
+        #[cfg(unix)]
+        match builder.create(&to) {
+            Err(e) if force && e.kind() == io::ErrorKind::AlreadyExists => {}
+            r => r.map_io_err(|| format!("Failed to create directory: {to:?}"))?,
+        };
+        copy.run((from, to))?;
+    } else {
+        compat::copy_single_file(&from, &to, &from_metadata, options)?;
     }
     Ok(())
 }
 
+/// Swaps the finished directory copies in for their destinations, or throws
+/// them away if anything went wrong.
+fn replace_trees(
+    trees: Vec<(PathBuf, PathBuf)>,
+    result: Result<(), Error>,
+    options: &Options,
+) -> Result<(), Error> {
+    if trees.is_empty() {
+        return result;
+    }
+    if result.is_err() || options.failures.any() {
+        // Cleaning up shouldn't be cancelled along with the copy.
+        RemoveOp::builder()
+            .files(trees.into_iter().map(|(temp, _)| temp))
+            .threads(options.threads)
+            .build()
+            .run()?;
+        return result;
+    }
+
+    let mut old_trees = Vec::new();
+    for (temp, to) in trees {
+        if let Some(old) = compat::replace_tree(&temp, &to)
+            .map_io_err(|| format!("Failed to move {temp:?} into place at {to:?}"))?
+        {
+            old_trees.push(old);
+        }
+    }
+    RemoveOp::builder()
+        .files(old_trees)
+        .threads(options.threads)
+        .build()
+        .run()
+}
+
+/// A hidden name that no other copy in flight is using.
+fn temp_name() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!(
+        ".fuc-{}-{}",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
 #[cfg(target_os = "linux")]
 mod compat {
     use std::{
         borrow::Cow,
         cell::{Cell, LazyCell},
-        ffi::{CStr, CString},
-        fs::File,
-        io,
-        mem::MaybeUninit,
+        collections::{hash_map::Entry, HashMap, HashSet},
+        ffi::{CStr, CString, OsStr},
+        fs::{File, Metadata},
+        io::{self, Read},
+        mem::{self, MaybeUninit},
         num::NonZeroUsize,
-        os::unix::io::{AsFd, OwnedFd},
-        path::Path,
+        os::unix::{
+            ffi::OsStrExt,
+            fs::{FileExt, MetadataExt},
+            io::{AsFd, AsRawFd, BorrowedFd, OwnedFd},
+        },
+        path::{Path, PathBuf},
+        sync::{Arc, Condvar, Mutex, PoisonError},
         thread,
         thread::JoinHandle,
     };
@@ -156,8 +654,12 @@ mod compat {
     use crossbeam_channel::{Receiver, Sender};
     use rustix::{
         fs::{
-            copy_file_range, mkdirat, openat, readlinkat, statx, symlinkat, AtFlags, FileType,
-            Mode, OFlags, RawDir, StatxFlags, CWD,
+            chmodat, chownat, copy_file_range, fgetxattr, flistxattr, fsetxattr, ftruncate,
+            getxattr, ioctl_ficlone, lgetxattr, linkat, listxattr, llistxattr, lsetxattr, makedev,
+            mkdirat, mknodat, openat, readlinkat, renameat, renameat_with, seek, setxattr, statx,
+            symlinkat, unlinkat, utimensat, AtFlags, FileType, Gid, Mode, OFlags, RawDir,
+            RenameFlags, SeekFrom, Statx, StatxFlags, StatxTimestamp, Timespec, Timestamps, Uid,
+            XattrFlags, CWD,
         },
         io::Errno,
         thread::{unshare, UnshareFlags},
@@ -165,10 +667,16 @@ mod compat {
 
     use crate::{
         ops::{
-            compat::DirectoryOp, concat_cstrs, get_file_type, join_cstr_paths, path_buf_to_cstring,
-            IoErr,
+            compat::DirectoryOp,
+            concat_cstrs,
+            copy::{
+                temp_name, Atomic, Backup, Filter, FollowSymlinks, Options, Preserve, Reflink,
+                Sparse, SpecialFiles, UnsupportedXattrs, Update,
+            },
+            get_file_type, get_mount, join_cstr_paths, path_buf_to_cstring, CancellationToken,
+            Failures, IoErr, Progress,
         },
-        Error,
+        Error, RemoveOp,
     };
 
     struct Impl<LF: FnOnce() -> (Sender<TreeNode>, JoinHandle<Result<(), Error>>)> {
@@ -176,10 +684,126 @@ mod compat {
         scheduling: LazyCell<(Sender<TreeNode>, JoinHandle<Result<(), Error>>), LF>,
     }
 
-    pub fn copy_impl<'a, 'b>() -> impl DirectoryOp<(Cow<'a, Path>, Cow<'b, Path>)> {
-        let scheduling = LazyCell::new(|| {
+    /// State shared by every thread participating in a copy.
+    // Mirrors the independent flags of `CopyOp`.
+    #[allow(clippy::struct_excessive_bools)]
+    struct Context {
+        update: Update,
+        backup: Backup,
+        backup_suffix: String,
+        preserve: Preserve,
+        reflink: Reflink,
+        sparse: Sparse,
+        special_files: SpecialFiles,
+        follow_symlinks: FollowSymlinks,
+        one_file_system: bool,
+        /// Whether regular files are written under a temporary name first.
+        atomic: bool,
+        mirror: bool,
+        filter: Arc<Filter>,
+        progress: Arc<dyn Progress>,
+        dry_run: bool,
+        failures: Failures,
+        /// Only used to remove extraneous directories when mirroring.
+        threads: Option<NonZeroUsize>,
+        cancel: CancellationToken,
+        hard_links: Arc<HardLinks>,
+    }
+
+    /// Maps the `(dev, ino)` of files with multiple links to their first copy
+    /// so that the remaining links can be recreated instead of copied.
+    #[derive(Debug, Default)]
+    pub struct HardLinks(Mutex<HashMap<(u64, u64), Arc<FirstCopy>>>);
+
+    /// The first copy of a file with multiple links, which the other links wait
+    /// on until it exists.
+    #[derive(Debug)]
+    struct FirstCopy {
+        path: CString,
+        /// Unset while the copy is in progress, then whether it succeeded.
+        done: Mutex<Option<bool>>,
+        ready: Condvar,
+    }
+
+    impl FirstCopy {
+        const fn new(path: CString) -> Self {
+            Self {
+                path,
+                done: Mutex::new(None),
+                ready: Condvar::new(),
+            }
+        }
+
+        fn publish(&self, succeeded: bool) {
+            *self.done.lock().unwrap_or_else(PoisonError::into_inner) = Some(succeeded);
+            self.ready.notify_all();
+        }
+
+        /// Blocks until the copy is finished, returning whether it can be linked
+        /// to.
+        fn wait(&self) -> bool {
+            let done = self
+                .ready
+                .wait_while(
+                    self.done.lock().unwrap_or_else(PoisonError::into_inner),
+                    |done| done.is_none(),
+                )
+                .unwrap_or_else(PoisonError::into_inner);
+            *done == Some(true)
+        }
+    }
+
+    impl Context {
+        fn new(
+            Options {
+                update,
+                backup,
+                backup_suffix,
+                preserve,
+                reflink,
+                sparse,
+                special_files,
+                follow_symlinks,
+                one_file_system,
+                atomic,
+                mirror,
+                filter,
+                progress,
+                dry_run,
+                failures,
+                threads,
+                cancel,
+                hard_links,
+            }: Options,
+        ) -> Self {
+            Self {
+                update,
+                backup,
+                backup_suffix,
+                preserve,
+                reflink,
+                sparse,
+                special_files,
+                follow_symlinks,
+                one_file_system,
+                // Directory trees are swapped in whole, so their contents can be written in place.
+                atomic: atomic == Atomic::Files,
+                mirror,
+                filter,
+                progress,
+                dry_run,
+                failures,
+                threads,
+                cancel,
+                hard_links,
+            }
+        }
+    }
+
+    pub fn copy_impl<'a, 'b>(options: Options) -> impl DirectoryOp<(Cow<'a, Path>, Cow<'b, Path>)> {
+        let scheduling = LazyCell::new(move || {
             let (tx, rx) = crossbeam_channel::unbounded();
-            (tx, thread::spawn(|| root_worker_thread(rx)))
+            (tx, thread::spawn(move || root_worker_thread(rx, options)))
         });
 
         Impl { scheduling }
@@ -191,11 +815,14 @@ mod compat {
         #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(self)))]
         fn run(&self, (from, to): (Cow<Path>, Cow<Path>)) -> Result<(), Error> {
             let (tasks, _) = &*self.scheduling;
+            let from = path_buf_to_cstring(from.into_owned())?;
             tasks
                 .send(TreeNode {
-                    from: path_buf_to_cstring(from.into_owned())?,
+                    root_len: from.as_bytes().len(),
+                    from,
                     to: path_buf_to_cstring(to.into_owned())?,
                     messages: tasks.clone(),
+                    ancestors: None,
                 })
                 .map_err(|_| Error::Internal)
         }
@@ -212,12 +839,56 @@ mod compat {
         }
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(from_metadata))
+    )]
+    pub fn copy_single_file(
+        from: &Path,
+        to: &Path,
+        from_metadata: &Metadata,
+        options: &Options,
+    ) -> Result<(), Error> {
+        let from = path_buf_to_cstring(from.to_path_buf())?;
+        let to = path_buf_to_cstring(to.to_path_buf())?;
+        // The paths are relative to the CWD, so there is no parent to report.
+        let no_parent = CString::default();
+        copy_one_file(
+            CWD,
+            CWD,
+            &from,
+            &to,
+            FileType::from_raw_mode(from_metadata.mode()),
+            &no_parent,
+            &no_parent,
+            &Cell::default(),
+            &Context {
+                atomic: options.atomic != Atomic::Never,
+                ..Context::new(options.clone())
+            },
+        )
+    }
+
+    /// Moves `temp` over `to`, returning where the previous destination ended up
+    /// if there was one.
+    pub fn replace_tree(temp: &Path, to: &Path) -> io::Result<Option<PathBuf>> {
+        match renameat_with(CWD, temp, CWD, to, RenameFlags::EXCHANGE) {
+            Ok(()) => Ok(Some(temp.to_path_buf())),
+            Err(Errno::NOENT) => renameat_with(CWD, temp, CWD, to, RenameFlags::NOREPLACE)
+                .map(|()| None)
+                .map_err(io::Error::from),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(tasks)))]
-    fn root_worker_thread(tasks: Receiver<TreeNode>) -> Result<(), Error> {
-        let mut available_parallelism = thread::available_parallelism()
-            .map(NonZeroUsize::get)
-            .unwrap_or(1)
+    fn root_worker_thread(tasks: Receiver<TreeNode>, options: Options) -> Result<(), Error> {
+        let mut available_parallelism = options
+            .threads
+            .or_else(|| thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get)
             - 1;
+        let ctx = &Context::new(options);
 
         thread::scope(|scope| {
             let mut threads = Vec::with_capacity(available_parallelism);
@@ -229,6 +900,11 @@ mod compat {
                 for node in &tasks {
                     let root_to_inode = if let Some(root_to_inode) = root_to_inode {
                         root_to_inode
+                    } else if ctx.dry_run {
+                        // Nothing gets created, so there's no copy to stumble into. Zero is
+                        // never a valid inode.
+                        root_to_inode = Some(0);
+                        0
                     } else {
                         let to_dir = openat(
                             CWD,
@@ -255,19 +931,20 @@ mod compat {
                             available_parallelism -= 1;
                             threads.push(scope.spawn({
                                 let tasks = tasks.clone();
-                                move || worker_thread(tasks, root_to_inode)
+                                move || worker_thread(tasks, root_to_inode, ctx)
                             }));
                         }
                     };
                     maybe_spawn();
 
-                    copy_dir(
+                    ctx.failures.recover(copy_dir(
                         node,
                         root_to_inode,
                         &mut buf,
                         &symlink_buf_cache,
+                        ctx,
                         maybe_spawn,
-                    )?;
+                    ))?;
                 }
             }
 
@@ -278,158 +955,694 @@ mod compat {
         })
     }
 
-    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(tasks)))]
-    fn worker_thread(tasks: Receiver<TreeNode>, root_to_inode: u64) -> Result<(), Error> {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(tasks, ctx))
+    )]
+    fn worker_thread(
+        tasks: Receiver<TreeNode>,
+        root_to_inode: u64,
+        ctx: &Context,
+    ) -> Result<(), Error> {
         unshare(UnshareFlags::FILES).map_io_err(|| "Failed to unshare FD table.")?;
 
         let mut buf = [MaybeUninit::<u8>::uninit(); 8192];
         let symlink_buf_cache = Cell::new(Vec::new());
         for node in tasks {
-            copy_dir(node, root_to_inode, &mut buf, &symlink_buf_cache, || {})?;
+            ctx.failures.recover(copy_dir(
+                node,
+                root_to_inode,
+                &mut buf,
+                &symlink_buf_cache,
+                ctx,
+                || {},
+            ))?;
         }
         Ok(())
     }
 
     #[cfg_attr(
         feature = "tracing",
-        tracing::instrument(level = "trace", skip(messages, buf, symlink_buf_cache, maybe_spawn))
+        tracing::instrument(
+            level = "trace",
+            skip(messages, buf, symlink_buf_cache, ctx, maybe_spawn)
+        )
     )]
     fn copy_dir(
-        TreeNode { from, to, messages }: TreeNode,
+        TreeNode {
+            from,
+            to,
+            root_len,
+            messages,
+            ancestors,
+        }: TreeNode,
         root_to_inode: u64,
         buf: &mut [MaybeUninit<u8>],
         symlink_buf_cache: &Cell<Vec<u8>>,
+        ctx: &Context,
         mut maybe_spawn: impl FnMut(),
     ) -> Result<(), Error> {
-        let from_dir = openat(
-            CWD,
-            &from,
-            OFlags::RDONLY | OFlags::DIRECTORY | OFlags::NOFOLLOW,
-            Mode::empty(),
-        )
-        .map_io_err(|| format!("Failed to open directory: {from:?}"))?;
-        let to_dir = openat(
-            CWD,
-            &to,
-            OFlags::RDONLY | OFlags::DIRECTORY | OFlags::PATH,
-            Mode::empty(),
-        )
-        .map_io_err(|| format!("Failed to open directory: {to:?}"))?;
+        let preserve = ctx.preserve;
+        let follow_symlinks = ctx.follow_symlinks == FollowSymlinks::Always;
+        let (from_dir, ancestors) = open_source(&from, ancestors, follow_symlinks)?;
+        let to_dir = open_destination(&to, ctx)?;
+        // Stat before reading the directory so its access time is still intact.
+        let from_metadata = if preserve.any() && !ctx.dry_run {
+            Some(stat_dir(&from_dir, &from, preserve)?)
+        } else {
+            None
+        };
+
+        let mount = ctx
+            .one_file_system
+            .then(|| get_mount(&from_dir, c"", &from))
+            .transpose()?;
 
+        // Everything in the source, so that whatever else is in the destination can be removed.
+        let mut names = ctx.mirror.then(HashSet::new);
         let mut raw_dir = RawDir::new(&from_dir, buf);
         while let Some(file) = raw_dir.next() {
-            let file = file.map_io_err(|| format!("Failed to read directory: {from:?}"))?;
+            ctx.cancel.check()?;
+            let Some(file) = ctx
+                .failures
+                .recover(file.map_io_err(|| format!("Failed to read directory: {from:?}")))?
+            else {
+                // Without the full listing, there's no telling what's extraneous.
+                names = None;
+                break;
+            };
+            if let Some(names) = &mut names {
+                names.insert(file.file_name().to_owned());
+            }
             if file.ino() == root_to_inode {
                 // Block recursive descent from parent into child (e.g. cp parent parent/child).
                 continue;
             }
-            {
-                let name = file.file_name();
-                if name == c"." || name == c".." {
-                    continue;
-                }
+            if matches!(file.file_name().to_bytes(), b"." | b"..") {
+                continue;
             }
 
-            let file_type = match file.file_type() {
-                FileType::Unknown => get_file_type(&from_dir, file.file_name(), &from)?,
-                t => t,
+            let file_type = resolve_file_type(
+                &from_dir,
+                file.file_name(),
+                file.file_type(),
+                &from,
+                follow_symlinks,
+            );
+            let Some(file_type) = ctx.failures.recover(file_type)? else {
+                continue;
             };
+            if is_excluded(&from, root_len, file.file_name(), file_type, ctx) {
+                continue;
+            }
             if file_type == FileType::Directory {
-                let from = concat_cstrs(&from, file.file_name());
-                let to = concat_cstrs(&to, file.file_name());
-
-                copy_one_dir(&from_dir, &from, &to)?;
+                let Some(Some((from, to))) = ctx.failures.recover(copy_subdir(
+                    &from_dir,
+                    file.file_name(),
+                    &from,
+                    &to,
+                    mount,
+                    ctx,
+                ))?
+                else {
+                    continue;
+                };
                 maybe_spawn();
                 messages
                     .send(TreeNode {
                         from,
                         to,
+                        root_len,
                         messages: messages.clone(),
+                        ancestors: ancestors.clone(),
                     })
                     .map_err(|_| Error::Internal)?;
-            } else {
-                copy_one_file(
+            } else if let Some(to_dir) = &to_dir {
+                ctx.failures.recover(copy_one_file(
                     &from_dir,
-                    &to_dir,
+                    to_dir,
+                    file.file_name(),
                     file.file_name(),
                     file_type,
                     &from,
                     &to,
                     symlink_buf_cache,
-                )?;
+                    ctx,
+                ))?;
+            } else {
+                ctx.failures.recover(plan_one_file(
+                    file.file_name(),
+                    file_type,
+                    &from,
+                    &to,
+                    ctx,
+                ))?;
             }
         }
-        Ok(())
-    }
 
-    #[cfg_attr(
-        feature = "tracing",
-        tracing::instrument(level = "trace", skip(from_dir))
-    )]
-    pub fn copy_one_dir(
-        from_dir: impl AsFd,
-        from_path: &CString,
-        to_path: &CString,
-    ) -> Result<(), Error> {
-        let from_mode = {
-            let from_metadata = statx(from_dir, c"", AtFlags::EMPTY_PATH, StatxFlags::MODE)
-                .map_io_err(|| format!("Failed to stat directory: {from_path:?}"))?;
-            Mode::from_raw_mode(from_metadata.stx_mode.into())
-        };
-        match mkdirat(CWD, to_path, from_mode) {
-            Err(Errno::EXIST) => {}
-            r => r.map_io_err(|| format!("Failed to create directory: {to_path:?}"))?,
-        };
+        if let Some(names) = names {
+            ctx.failures
+                .recover(remove_extraneous(&from, &to, root_len, &names, buf, ctx))?;
+        }
 
+        // Only now that all children have been created will the directory stop changing.
+        if let Some(from_metadata) = from_metadata {
+            preserve_dir_metadata(&from_dir, &from, &to, &from_metadata, preserve)?;
+        }
+        ctx.progress.dir_done();
         Ok(())
     }
 
-    #[cfg_attr(
-        feature = "tracing",
-        tracing::instrument(level = "trace", skip(from_dir, to_dir, symlink_buf_cache))
-    )]
-    fn copy_one_file(
+    fn stat_dir(from_dir: impl AsFd, from: &CString, preserve: Preserve) -> Result<Statx, Error> {
+        statx(from_dir, c"", AtFlags::EMPTY_PATH, preserve_flags(preserve))
+            .map_io_err(|| format!("Failed to stat directory: {from:?}"))
+    }
+
+    fn resolve_file_type(
         from_dir: impl AsFd,
-        to_dir: impl AsFd,
-        file_name: &CStr,
+        name: &CStr,
         file_type: FileType,
-        from_path: &CString,
-        to_path: &CString,
-        symlink_buf_cache: &Cell<Vec<u8>>,
+        from: &CString,
+        follow_symlinks: bool,
+    ) -> Result<FileType, Error> {
+        match file_type {
+            FileType::Unknown => get_file_type(from_dir, name, from),
+            FileType::Symlink if follow_symlinks => get_target_file_type(from_dir, name, from),
+            t => Ok(t),
+        }
+    }
+
+    /// Opens the directory being copied into, which dry runs don't create.
+    fn open_destination(to: &CString, ctx: &Context) -> Result<Option<OwnedFd>, Error> {
+        if ctx.dry_run {
+            ctx.progress
+                .planned(Path::new(OsStr::from_bytes(to.as_bytes())));
+            return Ok(None);
+        }
+        openat(
+            CWD,
+            to,
+            OFlags::RDONLY | OFlags::DIRECTORY | OFlags::PATH,
+            Mode::empty(),
+        )
+        .map(Some)
+        .map_io_err(|| format!("Failed to open directory: {to:?}"))
+    }
+
+    fn preserve_dir_metadata(
+        from_dir: impl AsFd,
+        from: &CString,
+        to: &CString,
+        from_metadata: &Statx,
+        preserve: Preserve,
     ) -> Result<(), Error> {
-        if file_type == FileType::Symlink {
-            copy_symlink(
-                from_dir,
-                to_dir,
-                file_name,
-                from_path,
-                to_path,
-                symlink_buf_cache,
+        let no_parent = CString::default();
+        preserve_metadata(CWD, to, from_metadata, preserve, &no_parent, || {
+            copy_xattrs(
+                XattrHandle::Fd(from_dir.as_fd()),
+                XattrHandle::Link(to),
+                preserve.unsupported_xattrs,
+                (&no_parent, from),
+                (&no_parent, to),
             )
+        })
+    }
+
+    /// Opens the directory being copied, adding it to its ancestors when
+    /// following symlinks.
+    fn open_source(
+        from: &CString,
+        ancestors: Option<Arc<Ancestor>>,
+        follow_symlinks: bool,
+    ) -> Result<(OwnedFd, Option<Arc<Ancestor>>), Error> {
+        let from_dir = openat(
+            CWD,
+            from,
+            if follow_symlinks {
+                OFlags::RDONLY | OFlags::DIRECTORY
+            } else {
+                OFlags::RDONLY | OFlags::DIRECTORY | OFlags::NOFOLLOW
+            },
+            Mode::empty(),
+        )
+        .map_io_err(|| format!("Failed to open directory: {from:?}"))?;
+        if !follow_symlinks {
+            return Ok((from_dir, None));
+        }
+
+        // Following symlinks is the only way to revisit a directory we're already in.
+        let from_metadata = statx(&from_dir, c"", AtFlags::EMPTY_PATH, StatxFlags::INO)
+            .map_io_err(|| format!("Failed to stat directory: {from:?}"))?;
+        let id = (
+            makedev(from_metadata.stx_dev_major, from_metadata.stx_dev_minor),
+            from_metadata.stx_ino,
+        );
+        if Ancestor::contains(ancestors.as_deref(), id) {
+            return Err(Error::SymlinkLoop {
+                file: Path::new(OsStr::from_bytes(from.as_bytes())).to_path_buf(),
+            });
+        }
+        let ancestors = Arc::new(Ancestor {
+            id,
+            parent: ancestors,
+        });
+        Ok((from_dir, Some(ancestors)))
+    }
+
+    fn is_excluded(
+        dir: &CString,
+        root_len: usize,
+        name: &CStr,
+        file_type: FileType,
+        ctx: &Context,
+    ) -> bool {
+        !ctx.filter.is_empty()
+            && ctx.filter.excludes(
+                &relative_path(dir, root_len, name),
+                file_type == FileType::Directory,
+            )
+    }
+
+    /// Creates the destination for a subdirectory, returning the paths its
+    /// contents should be copied between unless it's on another mount that
+    /// shouldn't be crossed.
+    fn copy_subdir(
+        from_dir: impl AsFd,
+        name: &CStr,
+        from: &CString,
+        to: &CString,
+        mount: Option<(u64, u64)>,
+        ctx: &Context,
+    ) -> Result<Option<(CString, CString)>, Error> {
+        let crosses_mount = match mount {
+            Some(mount) => get_mount(&from_dir, name, from)? != mount,
+            None => false,
+        };
+        let from = concat_cstrs(from, name);
+        let to = concat_cstrs(to, name);
+        if !ctx.dry_run {
+            copy_one_dir(&from_dir, &from, &to, ctx.preserve)?;
+        }
+
+        if crosses_mount {
+            // Only the mount point itself is copied.
+            if ctx.dry_run {
+                ctx.progress
+                    .planned(Path::new(OsStr::from_bytes(to.as_bytes())));
+            }
+            ctx.progress.dir_done();
+            return Ok(None);
+        }
+        Ok(Some((from, to)))
+    }
+
+    #[cold]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(names, buf, ctx))
+    )]
+    fn remove_extraneous(
+        from: &CString,
+        to: &CString,
+        root_len: usize,
+        names: &HashSet<CString>,
+        buf: &mut [MaybeUninit<u8>],
+        ctx: &Context,
+    ) -> Result<(), Error> {
+        let to_dir = match openat(
+            CWD,
+            to,
+            OFlags::RDONLY | OFlags::DIRECTORY | OFlags::NOFOLLOW,
+            Mode::empty(),
+        ) {
+            // Dry runs don't create the destination.
+            Err(Errno::NOENT) if ctx.dry_run => return Ok(()),
+            r => r.map_io_err(|| format!("Failed to open directory: {to:?}"))?,
+        };
+
+        let mut raw_dir = RawDir::new(&to_dir, buf);
+        while let Some(file) = raw_dir.next() {
+            ctx.cancel.check()?;
+            let Some(file) = ctx
+                .failures
+                .recover(file.map_io_err(|| format!("Failed to read directory: {to:?}")))?
+            else {
+                break;
+            };
+            let name = file.file_name();
+            if name == c"." || name == c".." || names.contains(name) || is_backup(name, names, ctx)
+            {
+                continue;
+            }
+
+            let file_type = match file.file_type() {
+                FileType::Unknown => get_file_type(&to_dir, name, to),
+                t => Ok(t),
+            };
+            let Some(file_type) = ctx.failures.recover(file_type)? else {
+                continue;
+            };
+            if is_excluded(from, root_len, name, file_type, ctx) {
+                continue;
+            }
+            ctx.failures
+                .recover(remove_extraneous_file(&to_dir, to, name, file_type, ctx))?;
+        }
+        Ok(())
+    }
+
+    /// Whether `name` is a backup of one of the source's `names`, which
+    /// mirroring leaves alone since the copy may have just made it.
+    /// Splits a `name.~N~` backup name into the backed up name and `N`.
+    fn split_numbered_backup(name: &[u8]) -> Option<(&[u8], &[u8])> {
+        let name = name.strip_suffix(b"~")?;
+        let digits = name.iter().rev().take_while(|b| b.is_ascii_digit()).count();
+        if digits == 0 {
+            return None;
+        }
+        let (name, n) = name.split_at(name.len() - digits);
+        Some((name.strip_suffix(b".~")?, n))
+    }
+
+    fn is_backup(name: &CStr, names: &HashSet<CString>, ctx: &Context) -> bool {
+        let name = name.to_bytes();
+        let backed_up = match ctx.backup {
+            Backup::Never => None,
+            Backup::Simple => name.strip_suffix(ctx.backup_suffix.as_bytes()),
+            Backup::Numbered => split_numbered_backup(name).map(|(name, _)| name),
+        };
+        backed_up
+            .and_then(|name| CString::new(name).ok())
+            .is_some_and(|name| names.contains(&name))
+    }
+
+    fn remove_extraneous_file(
+        to_dir: impl AsFd,
+        to: &CString,
+        name: &CStr,
+        file_type: FileType,
+        ctx: &Context,
+    ) -> Result<(), Error> {
+        let path = join_cstr_paths(to, name);
+        if file_type == FileType::Directory {
+            RemoveOp::builder()
+                .files([path])
+                .one_file_system(ctx.one_file_system)
+                .progress(ctx.progress.clone())
+                .dry_run(ctx.dry_run)
+                .continue_on_error(ctx.failures.continue_on_error())
+                .threads(ctx.threads)
+                .cancel(ctx.cancel.clone())
+                .build()
+                .run()
         } else {
-            let (from, to) = prep_regular_file(from_dir, to_dir, file_name, from_path, to_path)?;
-            if file_type == FileType::RegularFile {
-                copy_regular_file(from, to, file_name, from_path)
+            if ctx.dry_run {
+                ctx.progress.planned(&path);
             } else {
-                copy_any_file(from, to, file_name, from_path)
+                unlinkat(to_dir, name, AtFlags::empty())
+                    .map_io_err(|| format!("Failed to delete file: {path:?}"))?;
             }
+            ctx.progress.file_done(0);
+            Ok(())
         }
     }
 
     #[cfg_attr(
         feature = "tracing",
-        tracing::instrument(level = "trace", skip(from, to))
+        tracing::instrument(level = "trace", skip(from_dir))
+    )]
+    pub fn copy_one_dir(
+        from_dir: impl AsFd,
+        from_path: &CString,
+        to_path: &CString,
+        preserve: Preserve,
+    ) -> Result<(), Error> {
+        let from_mode = {
+            let from_metadata = statx(from_dir, c"", AtFlags::EMPTY_PATH, StatxFlags::MODE)
+                .map_io_err(|| format!("Failed to stat directory: {from_path:?}"))?;
+            Mode::from_raw_mode(from_metadata.stx_mode.into())
+        };
+        // Keep the directory writable until its contents have been copied.
+        let to_mode = if preserve.mode {
+            from_mode | Mode::RWXU
+        } else {
+            from_mode
+        };
+        match mkdirat(CWD, to_path, to_mode) {
+            Err(Errno::EXIST) => {}
+            r => r.map_io_err(|| format!("Failed to create directory: {to_path:?}"))?,
+        }
+
+        Ok(())
+    }
+
+    #[cold]
+    fn plan_one_file(
+        file_name: &CStr,
+        file_type: FileType,
+        from_path: &CString,
+        to_path: &CString,
+        ctx: &Context,
+    ) -> Result<(), Error> {
+        match file_type {
+            FileType::RegularFile | FileType::Symlink => {}
+            _ => match ctx.special_files {
+                SpecialFiles::Recreate => {}
+                SpecialFiles::Skip => return Ok(()),
+                SpecialFiles::Fail => {
+                    return Err(Error::SpecialFile {
+                        file: join_cstr_paths(from_path, file_name),
+                    });
+                }
+            },
+        }
+        ctx.progress.planned(&join_cstr_paths(to_path, file_name));
+        ctx.progress.file_done(0);
+        Ok(())
+    }
+
+    fn relative_path(dir: &CString, root_len: usize, name: &CStr) -> PathBuf {
+        let dir = &dir.as_bytes()[root_len..];
+        let dir = dir.strip_prefix(b"/").unwrap_or(dir);
+        Path::new(OsStr::from_bytes(dir)).join(OsStr::from_bytes(name.to_bytes()))
+    }
+
+    #[cold]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(dir)))]
+    fn get_target_file_type(
+        dir: impl AsFd,
+        file_name: &CStr,
+        path: &CString,
+    ) -> Result<FileType, Error> {
+        statx(dir, file_name, AtFlags::empty(), StatxFlags::TYPE)
+            .map_io_err(|| {
+                format!(
+                    "Failed to stat symlink target: {:?}",
+                    join_cstr_paths(path, file_name)
+                )
+            })
+            .map(|metadata| FileType::from_raw_mode(metadata.stx_mode.into()))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(from_dir, to_dir, symlink_buf_cache, ctx))
+    )]
+    fn copy_one_file(
+        from_dir: impl AsFd,
+        to_dir: impl AsFd,
+        from_name: &CStr,
+        to_name: &CStr,
+        file_type: FileType,
+        from_path: &CString,
+        to_path: &CString,
+        symlink_buf_cache: &Cell<Vec<u8>>,
+        ctx: &Context,
+    ) -> Result<(), Error> {
+        let preserve = ctx.preserve;
+        match file_type {
+            FileType::RegularFile => {
+                copy_one_regular_file(
+                    from_dir, to_dir, from_name, to_name, from_path, to_path, ctx,
+                )?;
+            }
+            FileType::Symlink => {
+                let from_metadata = statx(
+                    &from_dir,
+                    from_name,
+                    AtFlags::SYMLINK_NOFOLLOW,
+                    StatxFlags::TYPE
+                        | StatxFlags::SIZE
+                        | StatxFlags::MTIME
+                        | preserve_flags(preserve),
+                )
+                .map_io_err(|| {
+                    format!(
+                        "Failed to stat symlink: {:?}",
+                        join_cstr_paths(from_path, from_name)
+                    )
+                })?;
+                if !is_up_to_date(to_dir.as_fd(), to_name, to_path, &from_metadata, ctx)? {
+                    back_up(to_dir.as_fd(), to_name, to_path, ctx)?;
+                    copy_symlink(
+                        &from_dir,
+                        &to_dir,
+                        from_name,
+                        to_name,
+                        from_path,
+                        to_path,
+                        symlink_buf_cache,
+                        ctx.atomic,
+                    )?;
+                    if preserve.any() {
+                        preserve_unopened_metadata(
+                            &to_dir,
+                            &from_metadata,
+                            preserve,
+                            (from_path, from_name),
+                            (to_path, to_name),
+                        )?;
+                    }
+                }
+                ctx.progress.file_done(0);
+            }
+            _ => match ctx.special_files {
+                SpecialFiles::Recreate => {
+                    // The file type has already been resolved, so this only follows
+                    // symlinks that are meant to be followed.
+                    let from_metadata = statx(
+                        &from_dir,
+                        from_name,
+                        AtFlags::empty(),
+                        StatxFlags::TYPE
+                            | StatxFlags::MODE
+                            | StatxFlags::SIZE
+                            | StatxFlags::MTIME
+                            | preserve_flags(preserve),
+                    )
+                    .map_io_err(|| {
+                        format!(
+                            "Failed to stat file: {:?}",
+                            join_cstr_paths(from_path, from_name)
+                        )
+                    })?;
+                    if !is_up_to_date(to_dir.as_fd(), to_name, to_path, &from_metadata, ctx)? {
+                        back_up(to_dir.as_fd(), to_name, to_path, ctx)?;
+                        copy_special_file(&to_dir, to_name, to_path, &from_metadata, ctx.atomic)?;
+                        if preserve.any() {
+                            preserve_unopened_metadata(
+                                &to_dir,
+                                &from_metadata,
+                                preserve,
+                                (from_path, from_name),
+                                (to_path, to_name),
+                            )?;
+                        }
+                    }
+                    ctx.progress.file_done(0);
+                }
+                SpecialFiles::Skip => {}
+                SpecialFiles::Fail => {
+                    return Err(Error::SpecialFile {
+                        file: join_cstr_paths(from_path, from_name),
+                    });
+                }
+            },
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(from_dir, to_dir, ctx))
+    )]
+    fn copy_one_regular_file(
+        from_dir: impl AsFd,
+        to_dir: impl AsFd,
+        from_name: &CStr,
+        to_name: &CStr,
+        from_path: &CString,
+        to_path: &CString,
+        ctx: &Context,
+    ) -> Result<(), Error> {
+        let preserve = ctx.preserve;
+        if let Some((from, mut to, from_metadata)) = prep_regular_file(
+            from_dir,
+            to_dir.as_fd(),
+            from_name,
+            to_name,
+            from_path,
+            to_path,
+            ctx,
+        )? {
+            copy_regular_file(&from, &to.file, &from_metadata, from_name, from_path, ctx)?;
+            if preserve.any() {
+                to.link(to_name, to_path)?;
+                let name = to.name(to_name);
+                preserve_metadata(&to_dir, name, &from_metadata, preserve, to_path, || {
+                    copy_xattrs(
+                        XattrHandle::Fd(from.as_fd()),
+                        XattrHandle::Fd(to.file.as_fd()),
+                        preserve.unsupported_xattrs,
+                        (from_path, from_name),
+                        (to_path, name),
+                    )
+                })?;
+            }
+            to.commit(to_name, to_path)?;
+            ctx.progress.file_done(from_metadata.stx_size);
+        } else {
+            ctx.progress.file_done(0);
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(from, to, from_metadata, ctx))
     )]
     fn copy_regular_file(
-        from: OwnedFd,
-        to: OwnedFd,
+        from: &File,
+        to: &File,
+        from_metadata: &Statx,
         file_name: &CStr,
         from_path: &CString,
+        ctx: &Context,
     ) -> Result<(), Error> {
+        if ctx.reflink != Reflink::Never {
+            match ioctl_ficlone(to, from) {
+                Ok(()) => return Ok(()),
+                Err(_) if ctx.reflink == Reflink::Auto => {}
+                r => r.map_io_err(|| {
+                    format!(
+                        "Failed to clone file: {:?}",
+                        join_cstr_paths(from_path, file_name)
+                    )
+                })?,
+            }
+        }
+
+        let sparse = match ctx.sparse {
+            Sparse::Auto => from_metadata.stx_blocks * 512 < from_metadata.stx_size,
+            Sparse::Always => true,
+            Sparse::Never => false,
+        };
+        if sparse {
+            return copy_sparse_file(
+                from,
+                to,
+                from_metadata.stx_size,
+                ctx.sparse == Sparse::Always,
+                file_name,
+                from_path,
+            );
+        }
+
         let mut total_copied = 0;
         loop {
             let byte_copied =
-                match copy_file_range(&from, None, &to, None, usize::MAX / 2 - total_copied) {
+                match copy_file_range(from, None, to, None, usize::MAX / 2 - total_copied) {
                     Err(Errno::XDEV) if total_copied == 0 => {
                         return copy_any_file(from, to, file_name, from_path);
                     }
@@ -444,143 +1657,991 @@ mod compat {
             if byte_copied == 0 {
                 return Ok(());
             }
-            total_copied += byte_copied;
+            total_copied += byte_copied;
+        }
+    }
+
+    #[cold]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(from, to))
+    )]
+    #[allow(clippy::cast_possible_wrap)]
+    fn copy_sparse_file(
+        from: &File,
+        mut to: &File,
+        size: u64,
+        skip_zeros: bool,
+        file_name: &CStr,
+        from_path: &CString,
+    ) -> Result<(), Error> {
+        let context = || {
+            format!(
+                "Failed to copy file: {:?}",
+                join_cstr_paths(from_path, file_name)
+            )
+        };
+
+        let mut buf = if skip_zeros {
+            vec![0; 128 * 1024]
+        } else {
+            Vec::new()
+        };
+        let mut offset = 0;
+        while offset < size {
+            let data = match seek(from, SeekFrom::Data(offset as i64)) {
+                // The rest of the file is a hole.
+                Err(Errno::NXIO) => break,
+                r => r.map_io_err(context)?,
+            };
+            let hole = seek(from, SeekFrom::Hole(data as i64)).map_io_err(context)?;
+
+            if skip_zeros {
+                copy_skipping_zeros(from, to, data, hole, &mut buf)
+            } else {
+                seek(from, SeekFrom::Start(data))
+                    .and_then(|_| seek(to, SeekFrom::Start(data)))
+                    .map_err(io::Error::from)
+                    .and_then(|_| io::copy(&mut from.take(hole - data), &mut to))
+                    .map(|_| ())
+            }
+            .map_io_err(context)?;
+            offset = hole;
+        }
+        // Trailing holes aren't written, so they must be created by the size change.
+        ftruncate(to, size).map_io_err(context)
+    }
+
+    fn copy_skipping_zeros(
+        from: &File,
+        to: &File,
+        start: u64,
+        end: u64,
+        buf: &mut [u8],
+    ) -> io::Result<()> {
+        const BLOCK_SIZE: usize = 4096;
+
+        let mut offset = start;
+        while offset < end {
+            let len = usize::try_from(end - offset).map_or(buf.len(), |len| len.min(buf.len()));
+            let read = from.read_at(&mut buf[..len], offset)?;
+            if read == 0 {
+                break;
+            }
+
+            let mut block_offset = offset;
+            for block in buf[..read].chunks(BLOCK_SIZE) {
+                if block.iter().any(|&b| b != 0) {
+                    to.write_all_at(block, block_offset)?;
+                }
+                block_offset += block.len() as u64;
+            }
+            offset += read as u64;
+        }
+        Ok(())
+    }
+
+    #[cold]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(from, to))
+    )]
+    fn copy_any_file(
+        mut from: &File,
+        mut to: &File,
+        file_name: &CStr,
+        from_path: &CString,
+    ) -> Result<(), Error> {
+        io::copy(&mut from, &mut to)
+            .map_io_err(|| {
+                format!(
+                    "Failed to copy file: {:?}",
+                    join_cstr_paths(from_path, file_name)
+                )
+            })
+            .map(|_| ())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(from_dir, to_dir, ctx))
+    )]
+    fn prep_regular_file<'a>(
+        from_dir: impl AsFd,
+        to_dir: BorrowedFd<'a>,
+        from_name: &CStr,
+        to_name: &CStr,
+        from_path: &CString,
+        to_path: &CString,
+        ctx: &Context,
+    ) -> Result<Option<(File, StagedFile<'a>, Statx)>, Error> {
+        let from =
+            openat(&from_dir, from_name, OFlags::RDONLY, Mode::empty()).map_io_err(|| {
+                format!(
+                    "Failed to open file: {:?}",
+                    join_cstr_paths(from_path, from_name)
+                )
+            })?;
+
+        let from_metadata = statx(
+            from_dir,
+            from_name,
+            AtFlags::empty(),
+            StatxFlags::TYPE
+                | StatxFlags::MODE
+                | StatxFlags::NLINK
+                | StatxFlags::INO
+                | StatxFlags::SIZE
+                | StatxFlags::BLOCKS
+                | StatxFlags::MTIME
+                | preserve_flags(ctx.preserve),
+        )
+        .map_io_err(|| {
+            format!(
+                "Failed to stat file: {:?}",
+                join_cstr_paths(from_path, from_name)
+            )
+        })?;
+        if is_up_to_date(to_dir, to_name, to_path, &from_metadata, ctx)? {
+            return Ok(None);
+        }
+        back_up(to_dir, to_name, to_path, ctx)?;
+        let mode = Mode::from_raw_mode(from_metadata.stx_mode.into());
+        let create = |first_copy| {
+            let (file, staging) = if ctx.atomic {
+                create_staged(to_dir, to_name, mode)
+            } else {
+                let open = || {
+                    openat(
+                        to_dir,
+                        to_name,
+                        OFlags::CREATE | OFlags::TRUNC | OFlags::WRONLY | OFlags::NOFOLLOW,
+                        mode,
+                    )
+                };
+                match open() {
+                    // Replace symlinks instead of writing through them.
+                    Err(Errno::LOOP) => {
+                        unlinkat(to_dir, to_name, AtFlags::empty()).and_then(|()| open())
+                    }
+                    r => r,
+                }
+                .map(|file| (file, Staging::InPlace))
+            }
+            .map_io_err(|| {
+                format!(
+                    "Failed to open file: {:?}",
+                    join_cstr_paths(to_path, to_name)
+                )
+            })?;
+            Ok::<_, Error>(StagedFile {
+                file: File::from(file),
+                to_dir,
+                staging,
+                first_copy,
+            })
+        };
+
+        if from_metadata.stx_nlink <= 1 {
+            let to = create(None)?;
+            return Ok(Some((File::from(from), to, from_metadata)));
+        }
+
+        let path = path_buf_to_cstring(join_cstr_paths(to_path, to_name))?;
+        let mut hard_links = ctx
+            .hard_links
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match hard_links.entry((
+            makedev(from_metadata.stx_dev_major, from_metadata.stx_dev_minor),
+            from_metadata.stx_ino,
+        )) {
+            Entry::Occupied(first_copy) => {
+                let first_copy = first_copy.get().clone();
+                drop(hard_links);
+                if first_copy.wait() {
+                    link_to_first_copy(&first_copy.path, to_dir, to_name, to_path, ctx.atomic)?;
+                    Ok(None)
+                } else {
+                    // Fall back to an independent copy if the first one failed.
+                    let to = create(None)?;
+                    Ok(Some((File::from(from), to, from_metadata)))
+                }
+            }
+            Entry::Vacant(entry) => {
+                // The other links have to wait until the copy is committed so they
+                // never point at a half-written file.
+                let first_copy = entry.insert(Arc::new(FirstCopy::new(path))).clone();
+                drop(hard_links);
+                let to = create(Some(first_copy.clone()))
+                    .inspect_err(|_| first_copy.publish(false))?;
+                Ok(Some((File::from(from), to, from_metadata)))
+            }
+        }
+    }
+
+    /// Whether the update policy leaves the destination alone. Destinations
+    /// are compared without following symlinks, and ones of a different type
+    /// than their source are always replaced unless nothing may be.
+    fn is_up_to_date(
+        to_dir: BorrowedFd,
+        to_name: &CStr,
+        to_path: &CString,
+        from_metadata: &Statx,
+        ctx: &Context,
+    ) -> Result<bool, Error> {
+        if ctx.update == Update::Always {
+            return Ok(false);
+        }
+        let to_metadata = match statx(
+            to_dir,
+            to_name,
+            AtFlags::SYMLINK_NOFOLLOW,
+            StatxFlags::TYPE | StatxFlags::SIZE | StatxFlags::MTIME,
+        ) {
+            Err(Errno::NOENT) => return Ok(false),
+            r => r.map_io_err(|| {
+                format!(
+                    "Failed to stat file: {:?}",
+                    join_cstr_paths(to_path, to_name)
+                )
+            })?,
+        };
+        let file_type = |metadata: &Statx| FileType::from_raw_mode(metadata.stx_mode.into());
+        if file_type(&to_metadata) != file_type(from_metadata) {
+            return Ok(ctx.update == Update::Never);
+        }
+
+        let mtime = |metadata: &Statx| (metadata.stx_mtime.tv_sec, metadata.stx_mtime.tv_nsec);
+        Ok(match ctx.update {
+            Update::Always => false,
+            Update::Differ => {
+                to_metadata.stx_size == from_metadata.stx_size
+                    && mtime(&to_metadata) == mtime(from_metadata)
+            }
+            Update::Older => mtime(&to_metadata) >= mtime(from_metadata),
+            Update::Never => true,
+        })
+    }
+
+    /// Moves whatever is in the way of the copy aside under a backup name. Files
+    /// being replaced atomically stay put so that they never go missing.
+    fn back_up(
+        to_dir: BorrowedFd,
+        to_name: &CStr,
+        to_path: &CString,
+        ctx: &Context,
+    ) -> Result<(), Error> {
+        let move_to = |backup: &CStr, replace: bool| {
+            if ctx.atomic {
+                match linkat(to_dir, to_name, to_dir, backup, AtFlags::empty()) {
+                    Err(Errno::EXIST) if replace => {
+                        return unlinkat(to_dir, backup, AtFlags::empty()).and_then(|()| {
+                            linkat(to_dir, to_name, to_dir, backup, AtFlags::empty())
+                        });
+                    }
+                    // Directories can't be linked, so they'll have to be moved.
+                    Err(Errno::PERM) => {}
+                    r => return r,
+                }
+            }
+            renameat_with(
+                to_dir,
+                to_name,
+                to_dir,
+                backup,
+                if replace {
+                    RenameFlags::empty()
+                } else {
+                    RenameFlags::NOREPLACE
+                },
+            )
+        };
+        let backup_name = |suffix: &str| {
+            let mut name = to_name.to_bytes().to_vec();
+            name.extend_from_slice(suffix.as_bytes());
+            CString::new(name).unwrap()
+        };
+
+        let result = match ctx.backup {
+            Backup::Never => return Ok(()),
+            Backup::Simple => move_to(&backup_name(&ctx.backup_suffix), true),
+            Backup::Numbered => next_backup_number(to_dir, to_name).and_then(|first| {
+                (first..)
+                    .find_map(|n| match move_to(&backup_name(&format!(".~{n}~")), false) {
+                        Err(Errno::EXIST) => None,
+                        r => Some(r),
+                    })
+                    .unwrap()
+            }),
+        };
+        match result {
+            // Nothing to back up.
+            Err(Errno::NOENT) => Ok(()),
+            r => r.map_io_err(|| {
+                format!(
+                    "Failed to back up file: {:?}",
+                    join_cstr_paths(to_path, to_name)
+                )
+            }),
+        }
+    }
+
+    /// One past the highest `N` among the existing `.~N~` backups of the file,
+    /// so that new backups always sort after the old ones.
+    fn next_backup_number(to_dir: BorrowedFd, to_name: &CStr) -> rustix::io::Result<u64> {
+        // Most destinations don't exist yet, and then there's nothing to look for.
+        statx(to_dir, to_name, AtFlags::SYMLINK_NOFOLLOW, StatxFlags::empty())?;
+        let file_name = Path::new(OsStr::from_bytes(to_name.to_bytes()))
+            .file_name()
+            .map_or(&[][..], OsStrExt::as_bytes);
+        let dir = openat(
+            to_dir,
+            parent_name(to_name),
+            OFlags::RDONLY | OFlags::DIRECTORY,
+            Mode::empty(),
+        )?;
+
+        let mut buf = [MaybeUninit::<u8>::uninit(); 8192];
+        let mut raw_dir = RawDir::new(&dir, &mut buf);
+        let mut highest = 0;
+        while let Some(file) = raw_dir.next() {
+            let n = split_numbered_backup(file?.file_name().to_bytes())
+                .filter(|&(name, _)| name == file_name)
+                .and_then(|(_, n)| str::from_utf8(n).ok()?.parse::<u64>().ok());
+            highest = highest.max(n.unwrap_or(0));
+        }
+        Ok(highest.saturating_add(1))
+    }
+
+    /// Where a copied file is written before it appears under its final name.
+    enum Staging {
+        /// Already at its final name.
+        InPlace,
+        /// An `O_TMPFILE` that doesn't have a name yet.
+        Anonymous,
+        /// A hidden file next to the destination.
+        Named(CString),
+    }
+
+    struct StagedFile<'a> {
+        file: File,
+        to_dir: BorrowedFd<'a>,
+        staging: Staging,
+        /// Other links to the same file waiting until this copy is in place.
+        first_copy: Option<Arc<FirstCopy>>,
+    }
+
+    impl StagedFile<'_> {
+        /// Gives anonymous files a temporary name.
+        fn link(&mut self, to_name: &CStr, to_path: &CString) -> Result<(), Error> {
+            if matches!(self.staging, Staging::Anonymous) {
+                self.staging = Staging::Named(
+                    link_tmpfile(&self.file, self.to_dir, to_name).map_io_err(|| {
+                        format!(
+                            "Failed to link temporary file for: {:?}",
+                            join_cstr_paths(to_path, to_name)
+                        )
+                    })?,
+                );
+            }
+            Ok(())
+        }
+
+        /// The name the file can currently be reached by.
+        fn name<'c>(&'c self, to_name: &'c CStr) -> &'c CStr {
+            match &self.staging {
+                Staging::Named(temp) => temp,
+                Staging::InPlace | Staging::Anonymous => to_name,
+            }
+        }
+
+        /// Atomically replaces the destination with the copy.
+        fn commit(mut self, to_name: &CStr, to_path: &CString) -> Result<(), Error> {
+            self.link(to_name, to_path)?;
+            if let Staging::Named(temp) = mem::replace(&mut self.staging, Staging::InPlace) {
+                renameat(self.to_dir, &temp, self.to_dir, to_name).map_io_err(|| {
+                    format!(
+                        "Failed to move copy into place: {:?}",
+                        join_cstr_paths(to_path, to_name)
+                    )
+                })?;
+            }
+            if let Some(first_copy) = self.first_copy.take() {
+                first_copy.publish(true);
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for StagedFile<'_> {
+        fn drop(&mut self) {
+            // Don't leave half-written copies lying around.
+            if let Staging::Named(temp) = &self.staging {
+                let _ = unlinkat(self.to_dir, temp, AtFlags::empty());
+            }
+            if let Some(first_copy) = &self.first_copy {
+                first_copy.publish(false);
+            }
+        }
+    }
+
+    /// A hidden name in the same directory as `to_name`.
+    fn sibling_temp_name(to_name: &CStr) -> CString {
+        let to_name = Path::new(OsStr::from_bytes(to_name.to_bytes()));
+        let temp = to_name.with_file_name(temp_name());
+        CString::new(temp.as_os_str().as_bytes()).unwrap()
+    }
+
+    /// The directory containing `to_name`, relative to the same directory.
+    fn parent_name(to_name: &CStr) -> CString {
+        let to_name = Path::new(OsStr::from_bytes(to_name.to_bytes()));
+        match to_name.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => {
+                CString::new(parent.as_os_str().as_bytes()).unwrap()
+            }
+            _ => c".".to_owned(),
+        }
+    }
+
+    fn create_staged(
+        to_dir: BorrowedFd,
+        to_name: &CStr,
+        mode: Mode,
+    ) -> rustix::io::Result<(OwnedFd, Staging)> {
+        match openat(to_dir, parent_name(to_name), OFlags::TMPFILE | OFlags::WRONLY, mode) {
+            Ok(file) => return Ok((file, Staging::Anonymous)),
+            // Not every filesystem supports O_TMPFILE.
+            Err(Errno::OPNOTSUPP | Errno::ISDIR) => {}
+            Err(e) => return Err(e),
+        }
+        loop {
+            let temp = sibling_temp_name(to_name);
+            match openat(
+                to_dir,
+                &temp,
+                OFlags::CREATE | OFlags::EXCL | OFlags::WRONLY,
+                mode,
+            ) {
+                Err(Errno::EXIST) => {}
+                r => return r.map(|file| (file, Staging::Named(temp))),
+            }
+        }
+    }
+
+    #[cold]
+    fn link_tmpfile(
+        file: &File,
+        to_dir: BorrowedFd,
+        to_name: &CStr,
+    ) -> rustix::io::Result<CString> {
+        // Linking by descriptor requires CAP_DAC_READ_SEARCH, but going through procfs
+        // doesn't. Workers have their own descriptor tables, hence thread-self.
+        let proc_path = CString::new(format!("/proc/thread-self/fd/{}", file.as_raw_fd())).unwrap();
+        loop {
+            let temp = sibling_temp_name(to_name);
+            let linked = match linkat(CWD, &proc_path, to_dir, &temp, AtFlags::SYMLINK_FOLLOW) {
+                // Without procfs, this is all that's left.
+                Err(Errno::NOENT) => linkat(file, c"", to_dir, &temp, AtFlags::EMPTY_PATH),
+                r => r,
+            };
+            match linked {
+                Err(Errno::EXIST) => {}
+                r => return r.map(|()| temp),
+            }
         }
     }
 
     #[cold]
     #[cfg_attr(
         feature = "tracing",
-        tracing::instrument(level = "trace", skip(from, to))
+        tracing::instrument(level = "trace", skip(to_dir))
     )]
-    fn copy_any_file(
-        from: OwnedFd,
-        to: OwnedFd,
-        file_name: &CStr,
-        from_path: &CString,
+    fn link_to_first_copy(
+        first_copy: &CStr,
+        to_dir: impl AsFd,
+        to_name: &CStr,
+        to_path: &CString,
+        atomic: bool,
     ) -> Result<(), Error> {
-        io::copy(&mut File::from(from), &mut File::from(to))
-            .map_io_err(|| {
-                format!(
-                    "Failed to copy file: {:?}",
-                    join_cstr_paths(from_path, file_name)
-                )
-            })
-            .map(|_| ())
+        create_or_replace(&to_dir, to_name, atomic, |name| {
+            linkat(CWD, first_copy, &to_dir, name, AtFlags::empty())
+        })
+        .map_io_err(|| {
+            format!(
+                "Failed to create hard link: {:?}",
+                join_cstr_paths(to_path, to_name)
+            )
+        })
     }
 
+    #[cold]
+    #[allow(clippy::too_many_arguments)]
     #[cfg_attr(
         feature = "tracing",
-        tracing::instrument(level = "trace", skip(from_dir, to_dir))
+        tracing::instrument(level = "trace", skip(from_dir, to_dir, symlink_buf_cache))
     )]
-    fn prep_regular_file(
+    fn copy_symlink(
         from_dir: impl AsFd,
         to_dir: impl AsFd,
-        file_name: &CStr,
+        from_name: &CStr,
+        to_name: &CStr,
         from_path: &CString,
         to_path: &CString,
-    ) -> Result<(OwnedFd, OwnedFd), Error> {
-        let from =
-            openat(&from_dir, file_name, OFlags::RDONLY, Mode::empty()).map_io_err(|| {
+        symlink_buf_cache: &Cell<Vec<u8>>,
+        atomic: bool,
+    ) -> Result<(), Error> {
+        let from_symlink =
+            readlinkat(from_dir, from_name, symlink_buf_cache.take()).map_io_err(|| {
                 format!(
-                    "Failed to open file: {:?}",
-                    join_cstr_paths(from_path, file_name)
+                    "Failed to read symlink: {:?}",
+                    join_cstr_paths(from_path, from_name)
                 )
             })?;
 
-        let to = {
-            let from_mode = {
-                let from_metadata = statx(from_dir, file_name, AtFlags::empty(), StatxFlags::MODE)
-                    .map_io_err(|| {
-                        format!(
-                            "Failed to stat file: {:?}",
-                            join_cstr_paths(from_path, file_name)
-                        )
-                    })?;
-                Mode::from_raw_mode(from_metadata.stx_mode.into())
-            };
-            openat(
+        create_or_replace(&to_dir, to_name, atomic, |name| {
+            symlinkat(&from_symlink, &to_dir, name)
+        })
+        .map_io_err(|| {
+            format!(
+                "Failed to create symlink: {:?}",
+                join_cstr_paths(to_path, to_name)
+            )
+        })?;
+
+        symlink_buf_cache.set(from_symlink.into_bytes_with_nul());
+        Ok(())
+    }
+
+    #[cold]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(to_dir, from_metadata))
+    )]
+    fn copy_special_file(
+        to_dir: impl AsFd,
+        to_name: &CStr,
+        to_path: &CString,
+        from_metadata: &Statx,
+        atomic: bool,
+    ) -> Result<(), Error> {
+        let mode = from_metadata.stx_mode.into();
+        let file_type = FileType::from_raw_mode(mode);
+        create_or_replace(&to_dir, to_name, atomic, |name| {
+            mknodat(
                 &to_dir,
-                file_name,
-                OFlags::CREATE | OFlags::TRUNC | OFlags::WRONLY,
-                from_mode,
+                name,
+                file_type,
+                Mode::from_raw_mode(mode),
+                makedev(from_metadata.stx_rdev_major, from_metadata.stx_rdev_minor),
             )
-            .map_io_err(|| {
-                format!(
-                    "Failed to open file: {:?}",
-                    join_cstr_paths(to_path, file_name)
-                )
-            })?
-        };
+        })
+        .map_io_err(|| {
+            format!(
+                "Failed to create special file: {:?}",
+                join_cstr_paths(to_path, to_name)
+            )
+        })
+    }
 
-        Ok((from, to))
+    /// Creates a file through `create`, replacing whatever is in its place.
+    /// Atomic copies create it under a hidden name first and rename it over the
+    /// destination so that the old file never goes missing.
+    fn create_or_replace(
+        to_dir: impl AsFd,
+        to_name: &CStr,
+        atomic: bool,
+        create: impl Fn(&CStr) -> rustix::io::Result<()>,
+    ) -> rustix::io::Result<()> {
+        match create(to_name) {
+            // Only possible when copying over an existing tree.
+            Err(Errno::EXIST) if atomic => loop {
+                let temp = sibling_temp_name(to_name);
+                match create(&temp) {
+                    Err(Errno::EXIST) => {}
+                    r => {
+                        break r.and_then(|()| {
+                            renameat(&to_dir, &temp, &to_dir, to_name).inspect_err(|_| {
+                                let _ = unlinkat(&to_dir, &temp, AtFlags::empty());
+                            })
+                        });
+                    }
+                }
+            },
+            Err(Errno::EXIST) => {
+                unlinkat(&to_dir, to_name, AtFlags::empty()).and_then(|()| create(to_name))
+            }
+            r => r,
+        }
+    }
+
+    const fn preserve_flags(preserve: Preserve) -> StatxFlags {
+        let Preserve {
+            mode,
+            ownership,
+            timestamps,
+            xattr: _,
+            unsupported_xattrs: _,
+        } = preserve;
+
+        let mut flags = StatxFlags::TYPE;
+        if mode {
+            flags = flags.union(StatxFlags::MODE);
+        }
+        if ownership {
+            flags = flags.union(StatxFlags::UID).union(StatxFlags::GID);
+        }
+        if timestamps {
+            flags = flags.union(StatxFlags::ATIME).union(StatxFlags::MTIME);
+        }
+        flags
     }
 
     #[cold]
     #[cfg_attr(
         feature = "tracing",
-        tracing::instrument(level = "trace", skip(from_dir, to_dir, symlink_buf_cache))
+        tracing::instrument(level = "trace", skip(to_dir, from_metadata))
     )]
-    fn copy_symlink(
-        from_dir: impl AsFd,
+    fn preserve_metadata(
         to_dir: impl AsFd,
-        file_name: &CStr,
-        from_path: &CString,
+        to_name: &CStr,
+        from_metadata: &Statx,
+        preserve: Preserve,
         to_path: &CString,
-        symlink_buf_cache: &Cell<Vec<u8>>,
+        copy_xattrs: impl FnOnce() -> Result<(), Error>,
     ) -> Result<(), Error> {
-        let from_symlink =
-            readlinkat(from_dir, file_name, symlink_buf_cache.take()).map_io_err(|| {
+        // Ownership must come first since changing it can clear the setuid and setgid
+        // bits as well as file capabilities. ACLs are stored as xattrs and must be
+        // applied after the mode or they would be overwritten. Timestamps come last
+        // since every other change updates the ctime.
+        if preserve.ownership {
+            let uid = unsafe { Uid::from_raw(from_metadata.stx_uid) };
+            let gid = unsafe { Gid::from_raw(from_metadata.stx_gid) };
+            match chownat(
+                &to_dir,
+                to_name,
+                Some(uid),
+                Some(gid),
+                AtFlags::SYMLINK_NOFOLLOW,
+            ) {
+                // Unprivileged users can't give files away, but may still be in the group.
+                Err(Errno::PERM) => {
+                    match chownat(&to_dir, to_name, None, Some(gid), AtFlags::SYMLINK_NOFOLLOW) {
+                        Err(Errno::PERM) => {}
+                        r => r.map_io_err(|| {
+                            format!(
+                                "Failed to change group: {:?}",
+                                join_cstr_paths(to_path, to_name)
+                            )
+                        })?,
+                    }
+                }
+                r => r.map_io_err(|| {
+                    format!(
+                        "Failed to change owner: {:?}",
+                        join_cstr_paths(to_path, to_name)
+                    )
+                })?,
+            }
+        }
+
+        // Linux doesn't support symlink permissions.
+        if preserve.mode
+            && FileType::from_raw_mode(from_metadata.stx_mode.into()) != FileType::Symlink
+        {
+            chmodat(
+                &to_dir,
+                to_name,
+                Mode::from_raw_mode(from_metadata.stx_mode.into()),
+                AtFlags::empty(),
+            )
+            .map_io_err(|| {
                 format!(
-                    "Failed to read symlink: {:?}",
-                    join_cstr_paths(from_path, file_name)
+                    "Failed to change permissions: {:?}",
+                    join_cstr_paths(to_path, to_name)
                 )
             })?;
+        }
 
-        symlinkat(&from_symlink, &to_dir, file_name).map_io_err(|| {
-            format!(
-                "Failed to create symlink: {:?}",
-                join_cstr_paths(to_path, file_name)
+        if preserve.xattr {
+            copy_xattrs()?;
+        }
+
+        if preserve.timestamps {
+            fn timespec(time: StatxTimestamp) -> Timespec {
+                Timespec {
+                    tv_sec: time.tv_sec,
+                    tv_nsec: time.tv_nsec.into(),
+                }
+            }
+
+            utimensat(
+                &to_dir,
+                to_name,
+                &Timestamps {
+                    last_access: timespec(from_metadata.stx_atime),
+                    last_modification: timespec(from_metadata.stx_mtime),
+                },
+                AtFlags::SYMLINK_NOFOLLOW,
             )
-        })?;
+            .map_io_err(|| {
+                format!(
+                    "Failed to change timestamps: {:?}",
+                    join_cstr_paths(to_path, to_name)
+                )
+            })?;
+        }
 
-        symlink_buf_cache.set(from_symlink.into_bytes_with_nul());
+        Ok(())
+    }
+
+    /// Preserves the metadata of files that can't be opened, namely symlinks and
+    /// special files.
+    #[cold]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(to_dir, from_metadata))
+    )]
+    fn preserve_unopened_metadata(
+        to_dir: impl AsFd,
+        from_metadata: &Statx,
+        preserve: Preserve,
+        (from_path, from_name): (&CString, &CStr),
+        (to_path, to_name): (&CString, &CStr),
+    ) -> Result<(), Error> {
+        preserve_metadata(to_dir, to_name, from_metadata, preserve, to_path, || {
+            let from = path_buf_to_cstring(join_cstr_paths(from_path, from_name))?;
+            copy_xattrs(
+                if FileType::from_raw_mode(from_metadata.stx_mode.into()) == FileType::Symlink {
+                    XattrHandle::Link(&from)
+                } else {
+                    // Special files may have been reached through a symlink.
+                    XattrHandle::Path(&from)
+                },
+                XattrHandle::Link(&path_buf_to_cstring(join_cstr_paths(to_path, to_name))?),
+                preserve.unsupported_xattrs,
+                (from_path, from_name),
+                (to_path, to_name),
+            )
+        })
+    }
+
+    #[derive(Copy, Clone)]
+    enum XattrHandle<'a> {
+        Fd(BorrowedFd<'a>),
+        /// A path whose final component must not be followed.
+        Link(&'a CStr),
+        /// A path whose symlinks are all followed.
+        Path(&'a CStr),
+    }
+
+    impl XattrHandle<'_> {
+        fn list(self, names: &mut [u8]) -> rustix::io::Result<usize> {
+            match self {
+                Self::Fd(fd) => flistxattr(fd, names),
+                Self::Link(path) => llistxattr(path, names),
+                Self::Path(path) => listxattr(path, names),
+            }
+        }
+
+        fn get(self, name: &CStr, value: &mut [u8]) -> rustix::io::Result<usize> {
+            match self {
+                Self::Fd(fd) => fgetxattr(fd, name, value),
+                Self::Link(path) => lgetxattr(path, name, value),
+                Self::Path(path) => getxattr(path, name, value),
+            }
+        }
+
+        fn set(self, name: &CStr, value: &[u8]) -> rustix::io::Result<()> {
+            match self {
+                Self::Fd(fd) => fsetxattr(fd, name, value, XattrFlags::empty()),
+                Self::Link(path) => lsetxattr(path, name, value, XattrFlags::empty()),
+                Self::Path(path) => setxattr(path, name, value, XattrFlags::empty()),
+            }
+        }
+    }
+
+    #[cold]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(from, to))
+    )]
+    fn copy_xattrs(
+        from: XattrHandle,
+        to: XattrHandle,
+        unsupported_xattrs: UnsupportedXattrs,
+        (from_path, from_name): (&CString, &CStr),
+        (to_path, to_name): (&CString, &CStr),
+    ) -> Result<(), Error> {
+        /// Reads a value whose size isn't known up front, retrying if it grows
+        /// between querying its size and reading it.
+        fn read_sized<T: Copy + Default>(
+            mut read: impl FnMut(&mut [T]) -> rustix::io::Result<usize>,
+        ) -> rustix::io::Result<Vec<T>> {
+            loop {
+                let mut buf = vec![T::default(); read(&mut [])?];
+                match read(&mut buf) {
+                    Err(Errno::RANGE) => {}
+                    r => {
+                        buf.truncate(r?);
+                        return Ok(buf);
+                    }
+                }
+            }
+        }
+
+        let names = match read_sized(|names| from.list(names)) {
+            // Nothing to copy if the source doesn't support xattrs in the first place.
+            Err(Errno::NOTSUP) => return Ok(()),
+            r => r.map_io_err(|| {
+                format!(
+                    "Failed to list extended attributes: {:?}",
+                    join_cstr_paths(from_path, from_name)
+                )
+            })?,
+        };
+        for name in names.split_inclusive(|&c| c == 0) {
+            let Ok(name) = CStr::from_bytes_with_nul(name) else {
+                continue;
+            };
+
+            let value = match read_sized(|value| from.get(name, value)) {
+                // The attribute was removed after we listed it.
+                Err(Errno::NODATA) => continue,
+                r => r.map_io_err(|| {
+                    format!(
+                        "Failed to read extended attribute {name:?}: {:?}",
+                        join_cstr_paths(from_path, from_name)
+                    )
+                })?,
+            };
+            match to.set(name, &value) {
+                Err(Errno::NOTSUP) if unsupported_xattrs == UnsupportedXattrs::Skip => {}
+                r => r.map_io_err(|| {
+                    format!(
+                        "Failed to write extended attribute {name:?}: {:?}",
+                        join_cstr_paths(to_path, to_name)
+                    )
+                })?,
+            }
+        }
         Ok(())
     }
 
     struct TreeNode {
         from: CString,
         to: CString,
-        messages: Sender<TreeNode>,
+        /// The length of the path to the directory being copied, which the paths
+        /// matched by filters are relative to.
+        root_len: usize,
+        messages: Sender<Self>,
+        /// The directories leading up to this one, only tracked when following
+        /// symlinks.
+        ancestors: Option<Arc<Ancestor>>,
+    }
+
+    struct Ancestor {
+        id: (u64, u64),
+        parent: Option<Arc<Self>>,
+    }
+
+    impl Ancestor {
+        fn contains(mut ancestor: Option<&Self>, id: (u64, u64)) -> bool {
+            while let Some(Self {
+                id: ancestor_id,
+                parent,
+            }) = ancestor
+            {
+                if *ancestor_id == id {
+                    return true;
+                }
+                ancestor = parent.as_deref();
+            }
+            false
+        }
     }
 }
 
 #[cfg(not(target_os = "linux"))]
 mod compat {
-    use std::{borrow::Cow, fs, io, path::Path};
+    use std::{
+        borrow::Cow,
+        fs::{self, DirEntry, Metadata},
+        io,
+        path::{Path, PathBuf},
+    };
 
     use rayon::prelude::*;
 
     use crate::{
-        ops::{compat::DirectoryOp, IoErr},
-        Error,
+        ops::{
+            compat::DirectoryOp,
+            copy::{temp_name, Options},
+            IoErr,
+        },
+        Error, RemoveOp,
     };
 
-    struct Impl;
+    struct Impl {
+        options: Options,
+    }
+
+    pub fn copy_impl<'a, 'b>(options: Options) -> impl DirectoryOp<(Cow<'a, Path>, Cow<'b, Path>)> {
+        Impl { options }
+    }
+
+    pub fn copy_single_file(
+        from: &Path,
+        to: &Path,
+        from_metadata: &Metadata,
+        options: &Options,
+    ) -> Result<(), Error> {
+        #[cfg(unix)]
+        if from_metadata.is_symlink() {
+            let link =
+                fs::read_link(from).map_io_err(|| format!("Failed to read symlink: {from:?}"))?;
+            std::os::unix::fs::symlink(link, to)
+                .map_io_err(|| format!("Failed to create symlink: {to:?}"))?;
+            options.progress.file_done(0);
+            return Ok(());
+        }
+        #[cfg(not(unix))]
+        let _ = from_metadata;
+
+        let bytes = fs::copy(from, to).map_io_err(|| format!("Failed to copy file: {from:?}"))?;
+        options.progress.file_done(bytes);
+        Ok(())
+    }
 
-    pub fn copy_impl<'a, 'b>() -> impl DirectoryOp<(Cow<'a, Path>, Cow<'b, Path>)> {
-        Impl
+    /// Moves `temp` over `to`, returning where the previous destination ended up
+    /// if there was one.
+    pub fn replace_tree(temp: &Path, to: &Path) -> io::Result<Option<PathBuf>> {
+        // Without a way to exchange two paths, there's a moment where the destination
+        // doesn't exist.
+        let old = match to.symlink_metadata() {
+            Ok(_) => {
+                let old = to.with_file_name(temp_name());
+                fs::rename(to, &old)?;
+                Some(old)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        fs::rename(temp, to)?;
+        Ok(old)
     }
 
     impl DirectoryOp<(Cow<'_, Path>, Cow<'_, Path>)> for Impl {
         fn run(&self, (from, to): (Cow<Path>, Cow<Path>)) -> Result<(), Error> {
             copy_dir(
                 &from,
-                to,
+                &to,
+                &from,
+                &self.options,
                 #[cfg(unix)]
                 None,
             )
-            .map_io_err(|| format!("Failed to copy directory: {from:?}"))
         }
 
         fn finish(self) -> Result<(), Error> {
@@ -588,54 +2649,202 @@ mod compat {
         }
     }
 
-    fn copy_dir<P: AsRef<Path>, Q: AsRef<Path>>(
-        from: P,
-        to: Q,
+    fn copy_dir(
+        from: &Path,
+        to: &Path,
+        root: &Path,
+        options: &Options,
         #[cfg(unix)] root_to_inode: Option<u64>,
-    ) -> Result<(), io::Error> {
-        let to = to.as_ref();
-        match fs::create_dir(to) {
-            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
-            r => r?,
-        };
+    ) -> Result<(), Error> {
+        let Options {
+            ref progress,
+            dry_run,
+            ..
+        } = *options;
+        if dry_run {
+            progress.planned(to);
+        } else {
+            match fs::create_dir(to) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                r => r.map_io_err(|| format!("Failed to create directory: {to:?}"))?,
+            };
+        }
+        // Dry runs don't create anything to stumble into.
         #[cfg(unix)]
-        let root_to_inode = Some(maybe_compute_root_to_inode(to, root_to_inode)?);
+        let root_to_inode = if dry_run {
+            root_to_inode
+        } else {
+            Some(
+                maybe_compute_root_to_inode(to, root_to_inode)
+                    .map_io_err(|| format!("Failed to stat directory: {to:?}"))?,
+            )
+        };
 
-        from.as_ref()
-            .read_dir()?
+        from.read_dir()
+            .map_io_err(|| format!("Failed to read directory: {from:?}"))?
             .par_bridge()
-            .try_for_each(|dir_entry| -> io::Result<()> {
-                let dir_entry = dir_entry?;
+            .try_for_each(|dir_entry| -> Result<(), Error> {
+                options
+                    .failures
+                    .recover(copy_entry(
+                        from,
+                        to,
+                        dir_entry,
+                        root,
+                        options,
+                        #[cfg(unix)]
+                        root_to_inode,
+                    ))
+                    .map(|_| ())
+            })?;
+        if options.mirror {
+            options
+                .failures
+                .recover(remove_extraneous(from, to, root, options))?;
+        }
+        progress.dir_done();
+        Ok(())
+    }
 
-                #[cfg(unix)]
-                {
-                    use std::os::unix::fs::DirEntryExt;
-                    if Some(dir_entry.ino()) == root_to_inode {
-                        return Ok(());
-                    }
-                }
+    #[cold]
+    fn remove_extraneous(
+        from: &Path,
+        to: &Path,
+        root: &Path,
+        options: &Options,
+    ) -> Result<(), Error> {
+        let dir = match to.read_dir() {
+            // Dry runs don't create the destination.
+            Err(e) if e.kind() == io::ErrorKind::NotFound && options.dry_run => return Ok(()),
+            r => r.map_io_err(|| format!("Failed to read directory: {to:?}"))?,
+        };
+        for dir_entry in dir {
+            options.cancel.check()?;
+            options
+                .failures
+                .recover(remove_extraneous_entry(from, to, dir_entry, root, options))?;
+        }
+        Ok(())
+    }
 
-                let to = to.join(dir_entry.file_name());
-                let file_type = dir_entry.file_type()?;
+    fn remove_extraneous_entry(
+        from: &Path,
+        to: &Path,
+        dir_entry: io::Result<DirEntry>,
+        root: &Path,
+        options: &Options,
+    ) -> Result<(), Error> {
+        let dir_entry = dir_entry.map_io_err(|| format!("Failed to read directory: {to:?}"))?;
+        let from = from.join(dir_entry.file_name());
+        match from.symlink_metadata() {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            r => {
+                r.map_io_err(|| format!("Failed to read metadata for file: {from:?}"))?;
+                return Ok(());
+            }
+        }
 
-                #[cfg(unix)]
-                if file_type.is_dir() {
-                    copy_dir(dir_entry.path(), to, root_to_inode)?;
-                } else if file_type.is_symlink() {
-                    std::os::unix::fs::symlink(fs::read_link(dir_entry.path())?, to)?;
-                } else {
-                    fs::copy(dir_entry.path(), to)?;
-                }
+        let path = dir_entry.path();
+        let is_dir = dir_entry
+            .file_type()
+            .map_io_err(|| format!("Failed to read metadata for file: {path:?}"))?
+            .is_dir();
+        if !options.filter.is_empty()
+            && options
+                .filter
+                .excludes(from.strip_prefix(root).unwrap_or(&from), is_dir)
+        {
+            return Ok(());
+        }
+        if is_dir {
+            RemoveOp::builder()
+                .files([path])
+                .one_file_system(options.one_file_system)
+                .progress(options.progress.clone())
+                .dry_run(options.dry_run)
+                .continue_on_error(options.failures.continue_on_error())
+                .threads(options.threads)
+                .cancel(options.cancel.clone())
+                .build()
+                .run()
+        } else {
+            if options.dry_run {
+                options.progress.planned(&path);
+            } else {
+                fs::remove_file(&path).map_io_err(|| format!("Failed to delete file: {path:?}"))?;
+            }
+            options.progress.file_done(0);
+            Ok(())
+        }
+    }
 
-                #[cfg(not(unix))]
-                if file_type.is_dir() {
-                    copy_dir(dir_entry.path(), to)?;
-                } else {
-                    fs::copy(dir_entry.path(), to)?;
-                }
+    fn copy_entry(
+        from: &Path,
+        to: &Path,
+        dir_entry: io::Result<DirEntry>,
+        root: &Path,
+        options: &Options,
+        #[cfg(unix)] root_to_inode: Option<u64>,
+    ) -> Result<(), Error> {
+        options.cancel.check()?;
+        let Options {
+            ref filter,
+            ref progress,
+            dry_run,
+            ..
+        } = *options;
+        let dir_entry = dir_entry.map_io_err(|| format!("Failed to read directory: {from:?}"))?;
 
-                Ok(())
-            })
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirEntryExt;
+            if Some(dir_entry.ino()) == root_to_inode {
+                return Ok(());
+            }
+        }
+
+        let from = dir_entry.path();
+        let file_type = dir_entry
+            .file_type()
+            .map_io_err(|| format!("Failed to read metadata for file: {from:?}"))?;
+        if !filter.is_empty() {
+            let relative = from.strip_prefix(root).unwrap_or(&from);
+            if filter.excludes(relative, file_type.is_dir()) {
+                return Ok(());
+            }
+        }
+        let to = to.join(dir_entry.file_name());
+        if dry_run && !file_type.is_dir() {
+            progress.planned(&to);
+            progress.file_done(0);
+            return Ok(());
+        }
+
+        #[cfg(unix)]
+        if file_type.is_dir() {
+            copy_dir(&from, &to, root, options, root_to_inode)?;
+        } else if file_type.is_symlink() {
+            let link =
+                fs::read_link(&from).map_io_err(|| format!("Failed to read symlink: {from:?}"))?;
+            std::os::unix::fs::symlink(link, &to)
+                .map_io_err(|| format!("Failed to create symlink: {to:?}"))?;
+            progress.file_done(0);
+        } else {
+            progress.file_done(
+                fs::copy(&from, &to).map_io_err(|| format!("Failed to copy file: {from:?}"))?,
+            );
+        }
+
+        #[cfg(not(unix))]
+        if file_type.is_dir() {
+            copy_dir(&from, &to, root, options)?;
+        } else {
+            progress.file_done(
+                fs::copy(&from, &to).map_io_err(|| format!("Failed to copy file: {from:?}"))?,
+            );
+        }
+
+        Ok(())
     }
 
     #[cfg(unix)]
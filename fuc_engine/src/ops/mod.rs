@@ -1,14 +1,31 @@
-use std::{borrow::Cow, io};
-
-pub use copy::{copy_file, CopyOp};
+use std::{
+    borrow::Cow,
+    io, mem,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+pub use cancel::CancellationToken;
+pub use copy::{
+    copy_file, Atomic, Backup, CopyOp, Filter, FollowSymlinks, Preserve, Reflink, Sparse,
+    SpecialFiles, UnsupportedXattrs, Update,
+};
 #[cfg(target_os = "linux")]
-use linux::{concat_cstrs, get_file_type, join_cstr_paths, path_buf_to_cstring};
-pub use remove::{remove_file, RemoveOp};
+use linux::{concat_cstrs, get_file_type, get_mount, join_cstr_paths, path_buf_to_cstring};
+pub use mv::{move_file, MoveOp};
+pub use predicate::{FileKind, Predicate, Timestamp};
+pub use progress::{Progress, ProgressCounter};
+pub use remove::{remove_file, Confirm, Purge, RemoveOp, Trash};
 
 use crate::Error;
 
+mod background;
+mod cancel;
 mod copy;
+mod mv;
+mod predicate;
+mod progress;
 mod remove;
+mod trash;
 
 trait IoErr<Out> {
     fn map_io_err<I: Into<Cow<'static, str>>>(self, f: impl FnOnce() -> I) -> Out;
@@ -26,6 +43,76 @@ impl<T> IoErr<Result<T, Error>> for Result<T, io::Error> {
     }
 }
 
+/// Where operations that keep going after failures stash their errors.
+#[derive(Clone, Debug)]
+struct Failures(Option<Arc<Mutex<Vec<Error>>>>);
+
+impl Failures {
+    fn new(continue_on_error: bool) -> Self {
+        Self(continue_on_error.then(Arc::default))
+    }
+
+    /// Whether the operation keeps going after failures, for nested operations
+    /// to do the same.
+    const fn continue_on_error(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Records the error if the operation should keep going, in which case
+    /// `None` tells the caller to skip over whatever failed. Cancellation is
+    /// never recovered from.
+    fn recover<T>(&self, result: Result<T, Error>) -> Result<Option<T>, Error> {
+        match (result, &self.0) {
+            (Ok(t), _) => Ok(Some(t)),
+            (Err(e @ Error::Cancelled), _) | (Err(e), None) => Err(e),
+            // Nested operations report their own failures all at once.
+            (Err(Error::Aggregate { errors }), Some(failures)) => {
+                failures
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .extend(errors);
+                Ok(None)
+            }
+            (Err(e), Some(failures)) => {
+                failures
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Whether any errors have been recovered from so far.
+    fn any(&self) -> bool {
+        self.0.as_ref().is_some_and(|failures| {
+            !failures
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_empty()
+        })
+    }
+
+    /// Folds the recorded errors into the operation's final result.
+    fn finish(&self, result: Result<(), Error>) -> Result<(), Error> {
+        let Some(failures) = &self.0 else {
+            return result;
+        };
+        if matches!(result, Err(Error::Cancelled)) {
+            return result;
+        }
+        let mut errors = mem::take(&mut *failures.lock().unwrap_or_else(PoisonError::into_inner));
+        if let Err(e) = result {
+            errors.push(e);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Aggregate { errors })
+        }
+    }
+}
+
 #[cfg(target_os = "linux")]
 mod linux {
     use std::{
@@ -39,7 +126,7 @@ mod linux {
         path::{Path, PathBuf, MAIN_SEPARATOR},
     };
 
-    use rustix::fs::{statx, AtFlags, FileType, StatxFlags};
+    use rustix::fs::{makedev, statx, AtFlags, FileType, StatxFlags};
 
     use crate::{ops::IoErr, Error};
 
@@ -91,6 +178,39 @@ mod linux {
             })
             .map(|metadata| FileType::from_raw_mode(metadata.stx_mode.into()))
     }
+
+    /// Identifies the mount a file lives on, so that crossing into a different
+    /// one can be detected even for bind mounts of the same filesystem.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(dir)))]
+    pub fn get_mount(
+        dir: impl AsFd,
+        file_name: &CStr,
+        path: &CString,
+    ) -> Result<(u64, u64), Error> {
+        statx(
+            dir,
+            file_name,
+            AtFlags::SYMLINK_NOFOLLOW | AtFlags::EMPTY_PATH,
+            StatxFlags::MNT_ID,
+        )
+        .map_io_err(|| {
+            format!(
+                "Failed to stat file: {:?}",
+                join_cstr_paths(path, file_name)
+            )
+        })
+        .map(|metadata| {
+            (
+                makedev(metadata.stx_dev_major, metadata.stx_dev_minor),
+                // Kernels older than 5.8 don't report mount IDs.
+                if metadata.stx_mask & StatxFlags::MNT_ID.bits() == 0 {
+                    0
+                } else {
+                    metadata.stx_mnt_id
+                },
+            )
+        })
+    }
 }
 
 mod compat {
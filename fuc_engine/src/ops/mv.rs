@@ -0,0 +1,202 @@
+use std::{borrow::Cow, fmt::Debug, fs, io, marker::PhantomData, num::NonZeroUsize, path::Path};
+
+use typed_builder::TypedBuilder;
+
+use crate::{
+    ops::{CancellationToken, IoErr},
+    Atomic, CopyOp, Error, FollowSymlinks, Preserve, RemoveOp,
+};
+
+/// Moves a file or directory to this path.
+///
+/// # Errors
+///
+/// Returns the underlying I/O errors that occurred.
+pub fn move_file<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> Result<(), Error> {
+    MoveOp::builder()
+        .files([(Cow::Borrowed(from.as_ref()), Cow::Borrowed(to.as_ref()))])
+        .build()
+        .run()
+}
+
+#[derive(TypedBuilder, Debug)]
+pub struct MoveOp<
+    'a,
+    'b,
+    I1: Into<Cow<'a, Path>> + 'a,
+    I2: Into<Cow<'b, Path>> + 'b,
+    F: IntoIterator<Item = (I1, I2)>,
+> {
+    files: F,
+    #[builder(default = false)]
+    force: bool,
+    /// The most threads that may copy or delete at once when a move crosses
+    /// filesystems, defaulting to the available parallelism.
+    ///
+    /// Only honored on Linux.
+    #[builder(default)]
+    threads: Option<NonZeroUsize>,
+    /// Stops the move early with [`Error::Cancelled`] once cancelled. Sources
+    /// are only removed once everything has been copied, so a cancelled move
+    /// may leave a partial copy behind but never loses data.
+    #[builder(default)]
+    cancel: CancellationToken,
+    #[builder(default)]
+    _marker1: PhantomData<&'a I1>,
+    #[builder(default)]
+    _marker2: PhantomData<&'b I2>,
+}
+
+impl<
+    'a,
+    'b,
+    I1: Into<Cow<'a, Path>> + 'a,
+    I2: Into<Cow<'b, Path>> + 'b,
+    F: IntoIterator<Item = (I1, I2)>,
+> MoveOp<'a, 'b, I1, I2, F>
+{
+    /// Consume and run this move operation.
+    ///
+    /// Each file is renamed into place when possible. Files on a different
+    /// filesystem than their destination are instead copied over in a single
+    /// parallel pass and then removed, again in a single parallel pass.
+    /// Forced moves replace existing destinations instead of merging into
+    /// them, and only once their copy is complete. As with a rename, non-empty
+    /// directories are never replaced and files and directories never replace
+    /// each other.
+    ///
+    /// # Errors
+    ///
+    /// Returns the underlying I/O errors that occurred.
+    pub fn run(self) -> Result<(), Error> {
+        let Self {
+            files,
+            force,
+            threads,
+            cancel,
+            _marker1: _,
+            _marker2: _,
+        } = self;
+
+        let mut cross_device = Vec::new();
+        for (from, to) in files {
+            cancel.check()?;
+            let from = from.into();
+            let to = to.into();
+            if !rename(&from, &to, force)? {
+                cross_device.push((from, to));
+            }
+        }
+        if cross_device.is_empty() {
+            return Ok(());
+        }
+
+        if force {
+            for (from, to) in &cross_device {
+                check_replaceable(from, to)
+                    .map_io_err(|| format!("Failed to move {from:?} to {to:?}"))?;
+            }
+        }
+        CopyOp::builder()
+            .files(cross_device.iter().map(|(from, to)| (&**from, &**to)))
+            .force(force)
+            .atomic(if force { Atomic::Trees } else { Atomic::Never })
+            .preserve(Preserve {
+                mode: true,
+                ownership: true,
+                timestamps: true,
+                xattr: true,
+                ..Preserve::default()
+            })
+            .follow_symlinks(FollowSymlinks::Never)
+            .threads(threads)
+            .cancel(cancel.clone())
+            .build()
+            .run()?;
+        RemoveOp::builder()
+            .files(cross_device.into_iter().map(|(from, _)| from))
+            .threads(threads)
+            .cancel(cancel)
+            .build()
+            .run()
+    }
+}
+
+/// Refuses the replacements a rename would refuse, so that moves across
+/// filesystems fail the same way before anything is copied.
+fn check_replaceable(from: &Path, to: &Path) -> io::Result<()> {
+    let to_metadata = match to.symlink_metadata() {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        r => r?,
+    };
+    match (from.symlink_metadata()?.is_dir(), to_metadata.is_dir()) {
+        (true, true) if to.read_dir()?.next().is_some() => {
+            Err(io::ErrorKind::DirectoryNotEmpty.into())
+        }
+        (true, false) => Err(io::ErrorKind::NotADirectory.into()),
+        (false, true) => Err(io::ErrorKind::IsADirectory.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Returns `false` if the move crosses filesystems and therefore has to be
+/// done by hand.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "trace"))]
+fn rename(from: &Path, to: &Path, force: bool) -> Result<bool, Error> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)
+            .map_io_err(|| format!("Failed to create parent directory: {parent:?}"))?;
+    }
+
+    match compat::rename(from, to, force) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(Error::AlreadyExists {
+            file: to.to_path_buf(),
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && from.symlink_metadata().is_err() => {
+            Err(Error::NotFound {
+                file: from.to_path_buf(),
+            })
+        }
+        r => r
+            .map_io_err(|| format!("Failed to move {from:?} to {to:?}"))
+            .map(|()| true),
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod compat {
+    use std::{io, path::Path};
+
+    use rustix::fs::{renameat_with, RenameFlags, CWD};
+
+    pub fn rename(from: &Path, to: &Path, force: bool) -> io::Result<()> {
+        renameat_with(
+            CWD,
+            from,
+            CWD,
+            to,
+            if force {
+                RenameFlags::empty()
+            } else {
+                RenameFlags::NOREPLACE
+            },
+        )
+        .map_err(io::Error::from)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod compat {
+    use std::{fs, io, path::Path};
+
+    pub fn rename(from: &Path, to: &Path, force: bool) -> io::Result<()> {
+        // Not atomic, but there's no portable way to refuse to replace the
+        // destination.
+        if !force && to.symlink_metadata().is_ok() {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        fs::rename(from, to)
+    }
+}
@@ -0,0 +1,179 @@
+use std::{ffi::OsStr, fs, io, path::Path, time::SystemTime};
+
+use glob::{MatchOptions, Pattern};
+
+use crate::Error;
+
+/// Which of a file's timestamps to compare against.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Timestamp {
+    /// When the file's contents last changed.
+    Modified,
+    /// When the file was last read.
+    Accessed,
+}
+
+/// The kinds of files a [`Predicate`] can be limited to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FileKind {
+    /// Regular files.
+    File,
+    /// Directories.
+    Directory,
+    /// Symbolic links.
+    Symlink,
+    /// Devices, sockets, and FIFOs.
+    Other,
+}
+
+/// Tests files must pass to be removed, much like those of `find -delete`.
+///
+/// Every test that was added must pass. Directories are searched whether or
+/// not they pass, and are only removed if they pass and end up empty.
+#[derive(Clone, Debug, Default)]
+pub struct Predicate {
+    older_than: Option<(Timestamp, SystemTime)>,
+    newer_than: Option<(Timestamp, SystemTime)>,
+    names: Vec<Pattern>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    kinds: Vec<FileKind>,
+}
+
+/// The parts of a file's metadata a [`Predicate`] may need.
+pub struct Stats {
+    pub modified: SystemTime,
+    pub accessed: SystemTime,
+    pub size: u64,
+}
+
+impl Stats {
+    pub fn from_metadata(metadata: &fs::Metadata) -> io::Result<Self> {
+        Ok(Self {
+            modified: metadata.modified()?,
+            accessed: metadata.accessed()?,
+            size: metadata.len(),
+        })
+    }
+}
+
+impl FileKind {
+    pub(crate) fn from_file_type(file_type: fs::FileType) -> Self {
+        if file_type.is_file() {
+            Self::File
+        } else if file_type.is_dir() {
+            Self::Directory
+        } else if file_type.is_symlink() {
+            Self::Symlink
+        } else {
+            Self::Other
+        }
+    }
+}
+
+impl Predicate {
+    /// Pass files whose timestamp is before this time.
+    #[must_use]
+    pub const fn older_than(mut self, timestamp: Timestamp, time: SystemTime) -> Self {
+        self.older_than = Some((timestamp, time));
+        self
+    }
+
+    /// Pass files whose timestamp is after this time.
+    #[must_use]
+    pub const fn newer_than(mut self, timestamp: Timestamp, time: SystemTime) -> Self {
+        self.newer_than = Some((timestamp, time));
+        self
+    }
+
+    /// Pass files whose name matches this glob pattern, or any of the
+    /// patterns if there are several.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::InvalidPattern`] if the pattern isn't a valid glob.
+    pub fn name(mut self, pattern: &str) -> Result<Self, Error> {
+        self.names
+            .push(Pattern::new(pattern).map_err(|_| Error::InvalidPattern {
+                pattern: pattern.to_owned(),
+            })?);
+        Ok(self)
+    }
+
+    /// Pass files of at least this many bytes.
+    #[must_use]
+    pub const fn min_size(mut self, bytes: u64) -> Self {
+        self.min_size = Some(bytes);
+        self
+    }
+
+    /// Pass files of at most this many bytes.
+    #[must_use]
+    pub const fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Pass files of this kind, or any of the kinds if there are several.
+    #[must_use]
+    pub fn kind(mut self, kind: FileKind) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    pub(crate) const fn is_empty(&self) -> bool {
+        self.older_than.is_none()
+            && self.newer_than.is_none()
+            && self.names.is_empty()
+            && self.min_size.is_none()
+            && self.max_size.is_none()
+            && self.kinds.is_empty()
+    }
+
+    /// Only reads the file's metadata if the cheaper tests pass.
+    pub(crate) fn matches(
+        &self,
+        name: &OsStr,
+        kind: FileKind,
+        stats: impl FnOnce() -> Result<Stats, Error>,
+    ) -> Result<bool, Error> {
+        const OPTIONS: MatchOptions = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+
+        if !self.kinds.is_empty() && !self.kinds.contains(&kind) {
+            return Ok(false);
+        }
+        if !self.names.is_empty()
+            && !self
+                .names
+                .iter()
+                .any(|pattern| pattern.matches_path_with(Path::new(name), OPTIONS))
+        {
+            return Ok(false);
+        }
+        if self.older_than.is_none()
+            && self.newer_than.is_none()
+            && self.min_size.is_none()
+            && self.max_size.is_none()
+        {
+            return Ok(true);
+        }
+
+        let stats = stats()?;
+        let time = |timestamp| match timestamp {
+            Timestamp::Modified => stats.modified,
+            Timestamp::Accessed => stats.accessed,
+        };
+        Ok(self
+            .older_than
+            .is_none_or(|(timestamp, cutoff)| time(timestamp) < cutoff)
+            && self
+                .newer_than
+                .is_none_or(|(timestamp, cutoff)| time(timestamp) > cutoff)
+            && self.min_size.is_none_or(|min| stats.size >= min)
+            && self.max_size.is_none_or(|max| stats.size <= max))
+    }
+}
@@ -0,0 +1,68 @@
+use std::{
+    fmt::Debug,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Receives updates as an operation works its way through the file tree.
+///
+/// The methods are called from the worker threads in the middle of the hot
+/// loop, so they must be cheap: bump a counter and let some other thread
+/// render it.
+pub trait Progress: Debug + Send + Sync {
+    /// A file, symlink, or special file was copied or removed. Only regular
+    /// file copies have a non-zero size.
+    fn file_done(&self, bytes: u64) {
+        let _ = bytes;
+    }
+
+    /// A directory was copied or removed.
+    fn dir_done(&self) {}
+
+    /// Stands in for the work skipped by a dry run: called with each path
+    /// that would have been copied to or removed.
+    fn planned(&self, path: &Path) {
+        let _ = path;
+    }
+}
+
+/// Ignores all progress, which is the default.
+impl Progress for () {}
+
+/// A [`Progress`] implementation which tallies up everything it's told.
+#[derive(Debug, Default)]
+pub struct ProgressCounter {
+    files: AtomicU64,
+    dirs: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl ProgressCounter {
+    #[must_use]
+    pub fn files(&self) -> u64 {
+        self.files.load(Ordering::Relaxed)
+    }
+
+    #[must_use]
+    pub fn dirs(&self) -> u64 {
+        self.dirs.load(Ordering::Relaxed)
+    }
+
+    #[must_use]
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+impl Progress for ProgressCounter {
+    fn file_done(&self, bytes: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        if bytes > 0 {
+            self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    fn dir_done(&self) {
+        self.dirs.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -4,13 +4,22 @@ use std::{
     fmt::Debug,
     fs, io,
     marker::PhantomData,
-    path::{Path, MAIN_SEPARATOR_STR},
+    mem,
+    num::NonZeroUsize,
+    path::{Path, PathBuf, MAIN_SEPARATOR_STR},
+    sync::Arc,
 };
 
 use typed_builder::TypedBuilder;
 
 use crate::{
-    ops::{compat::DirectoryOp, IoErr},
+    ops::{
+        background::{self, STAGING_DIR},
+        compat::DirectoryOp,
+        predicate::{FileKind, Predicate, Stats},
+        trash::trash,
+        CancellationToken, Failures, IoErr, Progress,
+    },
     Error,
 };
 
@@ -29,6 +38,80 @@ pub fn remove_file<P: AsRef<Path>>(path: P) -> Result<(), Error> {
         .run()
 }
 
+/// Whether to move files to the trash instead of deleting them, following
+/// the freedesktop.org trash specification.
+///
+/// Only supported on Linux.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Trash {
+    /// Delete files for good.
+    #[default]
+    Never,
+    /// Fail for files that can't be moved to a trash on their own filesystem.
+    Always,
+    /// Delete files that can't be moved to a trash on their own filesystem.
+    OrDelete,
+}
+
+/// Decides whether a [`RemoveOp`] may remove each file, for example by asking
+/// the user.
+///
+/// Declining a file leaves it in place along with the directories above it
+/// that were being removed.
+pub trait Confirm: Debug + Send + Sync {
+    /// Called before removing each of the operation's files, where `is_dir`
+    /// means the directory's contents are about to be removed too.
+    fn confirm(&self, path: &Path, is_dir: bool) -> bool {
+        let _ = (path, is_dir);
+        true
+    }
+
+    /// Whether [`Confirm::confirm_entry`] should be called at all, since
+    /// building the path of every entry slows removal down.
+    fn confirms_entries(&self) -> bool {
+        false
+    }
+
+    /// Called before removing each file or descending into each directory
+    /// found inside the operation's directories, possibly from several
+    /// threads at once.
+    ///
+    /// Not honored on Windows.
+    fn confirm_entry(&self, path: &Path, is_dir: bool) -> bool {
+        let _ = (path, is_dir);
+        true
+    }
+
+    /// Called once a directory has been emptied, right before removing the
+    /// directory itself, if [`Confirm::confirms_entries`] is set. Declining
+    /// keeps the directory and the ones above it.
+    ///
+    /// Not honored on Windows.
+    fn confirm_emptied(&self, path: &Path) -> bool {
+        let _ = path;
+        true
+    }
+}
+
+/// Confirms every removal, which is the default.
+impl Confirm for () {}
+
+/// Deletes the staging directories of a [`RemoveOp`] running in the
+/// background once the operation returns, for example by handing them to a
+/// detached process.
+pub trait Purge: Debug + Send + Sync {
+    /// Called with every staging directory files were moved into. They may
+    /// also hold files that earlier purges didn't get to, which should be
+    /// deleted too.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors that kept the purge from starting.
+    fn purge(&self, staging: Vec<PathBuf>) -> Result<(), Error>;
+}
+
+// The flags are independent builder options, not a state machine.
+#[allow(clippy::struct_excessive_bools)]
 #[derive(TypedBuilder, Debug)]
 pub struct RemoveOp<'a, I: Into<Cow<'a, Path>> + 'a, F: IntoIterator<Item = I>> {
     files: F,
@@ -36,77 +119,433 @@ pub struct RemoveOp<'a, I: Into<Cow<'a, Path>> + 'a, F: IntoIterator<Item = I>>
     force: bool,
     #[builder(default = true)]
     preserve_root: bool,
+    /// Leave directories on other mounts untouched, which makes deleting their
+    /// parents fail.
+    ///
+    /// Only honored on Linux.
+    #[builder(default = false)]
+    one_file_system: bool,
+    /// Empty the directories instead of removing them, leaving the
+    /// directories themselves in place. Every file must be a directory.
+    #[builder(default = false)]
+    contents_only: bool,
+    /// Only remove the files passing these tests, searching every directory
+    /// for them. Directories to search can't be moved to the trash.
+    ///
+    /// Not supported on Windows.
+    #[builder(default)]
+    predicate: Predicate,
+    /// When a file can't be deleted for lack of permissions, give the owner
+    /// write and search permission on its parent directory and try again.
+    /// This removes trees made read-only by tools like Go's module cache.
+    ///
+    /// Only honored on Unix.
+    #[builder(default = false)]
+    fix_permissions: bool,
+    /// Move each file into a hidden staging directory next to it and return
+    /// right away, leaving this to delete the staging directories. Files that
+    /// can't be moved, such as mount points, are deleted before returning.
+    /// Ignored for dry runs, predicates, and when entries need confirming.
+    ///
+    /// Files left in a staging directory by a purge that never finished are
+    /// purged again by every later background removal of a file next to it,
+    /// or of the contents of the directory holding it.
+    ///
+    /// Only honored on Linux.
+    #[builder(default)]
+    background: Option<Arc<dyn Purge>>,
+    /// Move each file or directory to the trash as a whole instead of deleting
+    /// it.
+    #[builder(default)]
+    trash: Trash,
+    /// Asked before anything is removed.
+    #[builder(default = Arc::new(()))]
+    confirm: Arc<dyn Confirm>,
+    /// Notified of every file and directory removed.
+    #[builder(default = Arc::new(()))]
+    progress: Arc<dyn Progress>,
+    /// Walk the file trees without deleting anything, handing each path that
+    /// would have been removed to [`Progress::planned`] instead.
+    #[builder(default = false)]
+    dry_run: bool,
+    /// Keep removing the remaining files after a failure, reporting every
+    /// failure at the end in an [`Error::Aggregate`].
+    #[builder(default = false)]
+    continue_on_error: bool,
+    /// The most threads that may delete at once, defaulting to the available
+    /// parallelism. A single thread deletes everything in order.
+    ///
+    /// Only honored on Linux.
+    #[builder(default)]
+    threads: Option<NonZeroUsize>,
+    /// Stops the removal early with [`Error::Cancelled`] once cancelled.
+    #[builder(default)]
+    cancel: CancellationToken,
     #[builder(default)]
     _marker: PhantomData<&'a I>,
 }
 
+#[derive(Clone, Debug)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct Options {
+    walk: Walk,
+    predicate: Arc<Predicate>,
+    background: Option<Arc<dyn Purge>>,
+    trash: Trash,
+    confirm: Arc<dyn Confirm>,
+    confirm_entries: bool,
+    progress: Arc<dyn Progress>,
+    dry_run: bool,
+    failures: Failures,
+    threads: Option<NonZeroUsize>,
+    cancel: CancellationToken,
+}
+
+/// How the directories are walked and what is left of them.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+struct Walk {
+    one_file_system: bool,
+    contents_only: bool,
+    fix_permissions: bool,
+}
+
 impl<'a, I: Into<Cow<'a, Path>>, F: IntoIterator<Item = I>> RemoveOp<'a, I, F> {
     /// Consume and run this remove operation.
     ///
     /// # Errors
     ///
     /// Returns the underlying I/O errors that occurred.
-    pub fn run(self) -> Result<(), Error> {
-        let remove = compat::remove_impl();
-        let result = schedule_deletions(self, &remove);
-        remove.finish().and(result)
+    pub fn run(mut self) -> Result<(), Error> {
+        let predicate = Arc::new(mem::take(&mut self.predicate));
+        let options = Options {
+            walk: Walk {
+                one_file_system: self.one_file_system,
+                contents_only: self.contents_only,
+                fix_permissions: self.fix_permissions,
+            },
+            background: self.background.clone().filter(|_| {
+                !self.dry_run && predicate.is_empty() && !self.confirm.confirms_entries()
+            }),
+            predicate,
+            trash: self.trash,
+            confirm: self.confirm.clone(),
+            confirm_entries: self.confirm.confirms_entries(),
+            progress: self.progress.clone(),
+            dry_run: self.dry_run,
+            failures: Failures::new(self.continue_on_error),
+            threads: self.threads,
+            cancel: self.cancel.clone(),
+        };
+        let remove = compat::remove_impl(options.clone());
+        let mut staging = Vec::new();
+        let result = schedule_deletions(self, &options, &remove, &mut staging);
+        let mut result = remove.finish().and(result);
+        if let Some(purge) = options.background.as_ref().filter(|_| !staging.is_empty()) {
+            result = purge.purge(staging).and(result);
+        }
+        options.failures.finish(result)
     }
 }
 
 #[cfg_attr(
     feature = "tracing",
-    tracing::instrument(level = "trace", skip(files, remove))
+    tracing::instrument(level = "trace", skip(files, remove, staging))
 )]
 fn schedule_deletions<'a, I: Into<Cow<'a, Path>>, F: IntoIterator<Item = I>>(
     RemoveOp {
         files,
         force,
         preserve_root,
+        one_file_system: _,
+        contents_only: _,
+        predicate: _,
+        fix_permissions: _,
+        background: _,
+        trash: _,
+        confirm: _,
+        progress: _,
+        dry_run: _,
+        continue_on_error: _,
+        threads: _,
+        cancel: _,
         _marker: _,
     }: RemoveOp<'a, I, F>,
+    options: &Options,
     remove: &impl DirectoryOp<Cow<'a, Path>>,
+    staging: &mut Vec<PathBuf>,
 ) -> Result<(), Error> {
     for file in files {
-        let file = file.into();
-        if preserve_root && file == Path::new("/") {
-            return Err(Error::PreserveRoot);
-        }
-        let stripped_path = {
-            let trailing_slash_stripped = file
-                .as_os_str()
-                .as_encoded_bytes()
-                .strip_suffix(MAIN_SEPARATOR_STR.as_bytes())
-                .unwrap_or(file.as_os_str().as_encoded_bytes());
-            let path = unsafe { OsStr::from_encoded_bytes_unchecked(trailing_slash_stripped) };
-            Path::new(path)
-        };
+        options.cancel.check()?;
+        options.failures.recover(schedule_deletion(
+            file.into(),
+            force,
+            preserve_root,
+            options,
+            remove,
+            staging,
+        ))?;
+    }
+    Ok(())
+}
 
-        let is_dir = match stripped_path.symlink_metadata() {
-            Err(e) if e.kind() == io::ErrorKind::NotFound => {
-                if force {
-                    continue;
-                }
+fn schedule_deletion<'a>(
+    file: Cow<'a, Path>,
+    force: bool,
+    preserve_root: bool,
+    options: &Options,
+    remove: &impl DirectoryOp<Cow<'a, Path>>,
+    staging: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    if preserve_root && file == Path::new("/") {
+        return Err(Error::PreserveRoot);
+    }
+    let stripped_path = {
+        let trailing_slash_stripped = file
+            .as_os_str()
+            .as_encoded_bytes()
+            .strip_suffix(MAIN_SEPARATOR_STR.as_bytes())
+            .unwrap_or(file.as_os_str().as_encoded_bytes());
+        let path = unsafe { OsStr::from_encoded_bytes_unchecked(trailing_slash_stripped) };
+        Path::new(path)
+    };
 
-                return Err(Error::NotFound {
-                    file: stripped_path.to_path_buf(),
-                });
+    let metadata = match stripped_path.symlink_metadata() {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if force {
+                return Ok(());
             }
-            r => r,
+
+            return Err(Error::NotFound {
+                file: stripped_path.to_path_buf(),
+            });
         }
-        .map_io_err(|| format!("Failed to read metadata for file: {stripped_path:?}"))?
-        .is_dir();
+        r => r,
+    }
+    .map_io_err(|| format!("Failed to read metadata for file: {stripped_path:?}"))?;
+    let is_dir = metadata.is_dir();
 
-        if is_dir {
-            remove.run(
-                if file.as_os_str().len() == stripped_path.as_os_str().len() {
-                    file
-                } else {
-                    Cow::Owned(stripped_path.to_path_buf())
+    if !options.predicate.is_empty() {
+        if is_dir && options.trash != Trash::Never {
+            return Err(Error::Io {
+                error: io::ErrorKind::Unsupported.into(),
+                context: format!("Cannot move the matching files to the trash: {stripped_path:?}")
+                    .into(),
+            });
+        }
+        // Directories are tested as they're searched.
+        if !is_dir
+            && !options.predicate.matches(
+                stripped_path
+                    .file_name()
+                    .unwrap_or(stripped_path.as_os_str()),
+                FileKind::from_file_type(metadata.file_type()),
+                || {
+                    Stats::from_metadata(&metadata).map_io_err(|| {
+                        format!("Failed to read metadata for file: {stripped_path:?}")
+                    })
                 },
-            )?;
+            )?
+        {
+            return Ok(());
+        }
+    }
+
+    if !options.confirm.confirm(stripped_path, is_dir) {
+        return Ok(());
+    }
+
+    if options.walk.contents_only && !is_dir {
+        return Err(Error::Io {
+            error: io::ErrorKind::NotADirectory.into(),
+            context: format!("Cannot remove the contents of a non-directory: {stripped_path:?}")
+                .into(),
+        });
+    }
+
+    if options.trash != Trash::Never {
+        if options.walk.contents_only {
+            return trash_contents(stripped_path, options);
+        }
+        if move_to_trash(stripped_path, is_dir, options)? {
+            return Ok(());
+        }
+    }
+
+    if options.background.is_some() && stage(stripped_path, is_dir, options, staging)? {
+        return Ok(());
+    }
+
+    if is_dir {
+        remove.run(
+            if file.as_os_str().len() == stripped_path.as_os_str().len() {
+                file
+            } else {
+                Cow::Owned(stripped_path.to_path_buf())
+            },
+        )?;
+    } else {
+        if options.dry_run {
+            options.progress.planned(stripped_path);
         } else {
-            fs::remove_file(stripped_path)
-                .map_io_err(|| format!("Failed to delete file: {stripped_path:?}"))?;
+            let remove = || fs::remove_file(stripped_path);
+            match remove() {
+                Err(e)
+                    if e.kind() == io::ErrorKind::PermissionDenied
+                        && options.walk.fix_permissions
+                        && compat::grant_parent_permissions(stripped_path) =>
+                {
+                    remove()
+                }
+                r => r,
+            }
+            .map_io_err(|| format!("Failed to delete file: {stripped_path:?}"))?;
+        }
+        options.progress.file_done(0);
+    }
+    Ok(())
+}
+
+/// Moves the file, or the directory's entries if only its contents are being
+/// removed, out of the way for [`Purge::purge`] to delete. Returns
+/// `false` if something is left to delete in the foreground.
+fn stage(
+    path: &Path,
+    is_dir: bool,
+    options: &Options,
+    staging: &mut Vec<PathBuf>,
+) -> Result<bool, Error> {
+    let Some(dir) = (if options.walk.contents_only {
+        Some(path)
+    } else {
+        parent_dir(path)
+    }) else {
+        return Ok(false);
+    };
+    // Whatever an unfinished purge left behind is purged again, even if
+    // nothing new makes it into the staging directory.
+    let leftovers = dir.join(STAGING_DIR);
+    if !staging.contains(&leftovers)
+        && leftovers
+            .symlink_metadata()
+            .is_ok_and(|metadata| metadata.is_dir())
+    {
+        staging.push(leftovers);
+    }
+
+    let mut stage = |file: &Path, dir: &Path, is_dir: bool| {
+        let Some(staging_dir) = background::stage(file, dir)? else {
+            return Ok(false);
+        };
+        if !staging.contains(&staging_dir) {
+            staging.push(staging_dir);
+        }
+        if is_dir {
+            options.progress.dir_done();
+        } else {
+            options.progress.file_done(0);
+        }
+        Ok(true)
+    };
+
+    if !options.walk.contents_only {
+        return stage(path, dir, is_dir);
+    }
+
+    let mut staged_all = true;
+    for entry in fs::read_dir(path).map_io_err(|| format!("Failed to read directory: {path:?}"))? {
+        let entry = entry.map_io_err(|| format!("Failed to read directory: {path:?}"))?;
+        if entry.file_name() == STAGING_DIR {
+            continue;
+        }
+        let file = entry.path();
+        let is_dir = entry
+            .file_type()
+            .map_io_err(|| format!("Failed to read metadata for file: {file:?}"))?
+            .is_dir();
+        staged_all &= stage(&file, path, is_dir)?;
+    }
+    Ok(staged_all)
+}
+
+/// The directory holding the file, which is the current one for bare names.
+fn parent_dir(path: &Path) -> Option<&Path> {
+    let dir = path.parent().filter(|_| path.file_name().is_some())?;
+    Some(if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    })
+}
+
+/// Returns `false` if the file should be deleted instead.
+fn move_to_trash(path: &Path, is_dir: bool, options: &Options) -> Result<bool, Error> {
+    let trashed = options.dry_run || trash(path)?;
+    if trashed {
+        if options.dry_run {
+            options.progress.planned(path);
+        }
+        if is_dir {
+            options.progress.dir_done();
+        } else {
+            options.progress.file_done(0);
+        }
+        return Ok(true);
+    }
+    if options.trash == Trash::Always {
+        return Err(Error::Io {
+            error: io::ErrorKind::CrossesDevices.into(),
+            context: format!("No trash on the same filesystem as: {path:?}").into(),
+        });
+    }
+    Ok(false)
+}
+
+/// Moves each of the directory's entries to the trash on its own so the
+/// directory itself stays in place.
+fn trash_contents(dir: &Path, options: &Options) -> Result<(), Error> {
+    for entry in fs::read_dir(dir).map_io_err(|| format!("Failed to read directory: {dir:?}"))? {
+        options.cancel.check()?;
+        let entry = entry.map_io_err(|| format!("Failed to read directory: {dir:?}"))?;
+        let path = entry.path();
+        let is_dir = entry
+            .file_type()
+            .map_io_err(|| format!("Failed to read metadata for file: {path:?}"))?
+            .is_dir();
+        if options.confirm_entries && !options.confirm.confirm_entry(&path, is_dir) {
+            continue;
+        }
+
+        match options
+            .failures
+            .recover(move_to_trash(&path, is_dir, options))?
+        {
+            Some(false) if is_dir => {
+                options.failures.recover(
+                    RemoveOp::builder()
+                        .files([path.as_path()])
+                        .one_file_system(options.walk.one_file_system)
+                        .confirm(options.confirm.clone())
+                        .progress(options.progress.clone())
+                        .threads(options.threads)
+                        .cancel(options.cancel.clone())
+                        .build()
+                        .run(),
+                )?;
+            }
+            Some(false) => {
+                if options
+                    .failures
+                    .recover(
+                        fs::remove_file(&path)
+                            .map_io_err(|| format!("Failed to delete file: {path:?}")),
+                    )?
+                    .is_some()
+                {
+                    options.progress.file_done(0);
+                }
+            }
+            Some(true) | None => {}
         }
     }
     Ok(())
@@ -123,24 +562,36 @@ mod compat {
         mem::MaybeUninit,
         num::NonZeroUsize,
         os::{
-            fd::{AsFd, OwnedFd},
+            fd::{AsFd, AsRawFd, OwnedFd},
             unix::ffi::OsStrExt,
         },
         path::{Path, PathBuf},
-        sync::Arc,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
         thread,
         thread::JoinHandle,
+        time::{Duration, UNIX_EPOCH},
     };
 
     use crossbeam_channel::{Receiver, Sender};
     use rustix::{
-        fs::{openat, unlinkat, AtFlags, FileType, Mode, OFlags, RawDir, CWD},
+        fs::{
+            chmod, fchmod, fstat, openat, statx, unlinkat, AtFlags, FileType, Mode, OFlags, RawDir,
+            RawMode, StatxFlags, StatxTimestamp, CWD,
+        },
+        io::Errno,
+        path::Arg,
         thread::{unshare, UnshareFlags},
     };
 
     use crate::{
         ops::{
-            compat::DirectoryOp, concat_cstrs, get_file_type, join_cstr_paths, path_buf_to_cstring,
+            compat::DirectoryOp,
+            concat_cstrs, get_file_type, get_mount, join_cstr_paths, path_buf_to_cstring,
+            predicate::{FileKind, Stats},
+            remove::{parent_dir, Options},
             IoErr,
         },
         Error,
@@ -149,15 +600,20 @@ mod compat {
     struct Impl<LF: FnOnce() -> (Sender<TreeNode>, JoinHandle<Result<(), Error>>)> {
         #[allow(clippy::type_complexity)]
         scheduling: LazyCell<(Sender<TreeNode>, JoinHandle<Result<(), Error>>), LF>,
+        contents_only: bool,
     }
 
-    pub fn remove_impl<'a>() -> impl DirectoryOp<Cow<'a, Path>> {
-        let scheduling = LazyCell::new(|| {
+    pub fn remove_impl<'a>(options: Options) -> impl DirectoryOp<Cow<'a, Path>> {
+        let contents_only = options.walk.contents_only;
+        let scheduling = LazyCell::new(move || {
             let (tx, rx) = crossbeam_channel::unbounded();
-            (tx, thread::spawn(|| root_worker_thread(rx)))
+            (tx, thread::spawn(move || root_worker_thread(rx, options)))
         });
 
-        Impl { scheduling }
+        Impl {
+            scheduling,
+            contents_only,
+        }
     }
 
     impl<LF: FnOnce() -> (Sender<TreeNode>, JoinHandle<Result<(), Error>>)>
@@ -165,7 +621,10 @@ mod compat {
     {
         #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(self)))]
         fn run(&self, dir: Cow<Path>) -> Result<(), Error> {
-            let Self { ref scheduling } = *self;
+            let Self {
+                ref scheduling,
+                contents_only,
+            } = *self;
 
             let (tasks, _) = &**scheduling;
             tasks
@@ -173,13 +632,17 @@ mod compat {
                     path: path_buf_to_cstring(dir.into_owned())?,
                     parent: None,
                     messages: tasks.clone(),
+                    kept: AtomicBool::new(contents_only),
                 })
                 .map_err(|_| Error::Internal)
         }
 
         #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(self)))]
         fn finish(self) -> Result<(), Error> {
-            let Self { scheduling } = self;
+            let Self {
+                scheduling,
+                contents_only: _,
+            } = self;
 
             if let Ok((tasks, thread)) = LazyCell::into_inner(scheduling) {
                 drop(tasks);
@@ -190,14 +653,16 @@ mod compat {
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(tasks)))]
-    fn root_worker_thread(tasks: Receiver<TreeNode>) -> Result<(), Error> {
+    fn root_worker_thread(tasks: Receiver<TreeNode>, options: Options) -> Result<(), Error> {
         unshare(UnshareFlags::FILES | UnshareFlags::FS).map_io_err(|| "Failed to unshare I/O.")?;
 
-        let mut available_parallelism = thread::available_parallelism()
-            .map(NonZeroUsize::get)
-            .unwrap_or(1)
+        let mut available_parallelism = options
+            .threads
+            .or_else(|| thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get)
             - 1;
 
+        let options = &options;
         thread::scope(|scope| {
             let mut threads = Vec::with_capacity(available_parallelism);
 
@@ -216,13 +681,18 @@ mod compat {
                             available_parallelism -= 1;
                             threads.push(scope.spawn({
                                 let tasks = tasks.clone();
-                                || worker_thread(tasks)
+                                move || worker_thread(tasks, options)
                             }));
                         }
                     };
                     maybe_spawn();
 
-                    process_dir(message, &mut buf, maybe_spawn)?;
+                    options.failures.recover(process_dir(
+                        message,
+                        &mut buf,
+                        options,
+                        maybe_spawn,
+                    ))?;
                 }
             }
 
@@ -234,12 +704,14 @@ mod compat {
     }
 
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(tasks)))]
-    fn worker_thread(tasks: Receiver<TreeNode>) -> Result<(), Error> {
+    fn worker_thread(tasks: Receiver<TreeNode>, options: &Options) -> Result<(), Error> {
         unshare(UnshareFlags::FILES | UnshareFlags::FS).map_io_err(|| "Failed to unshare I/O.")?;
 
         let mut buf = [MaybeUninit::<u8>::uninit(); 8192];
         for message in tasks {
-            process_dir(message, &mut buf, || {})?;
+            options
+                .failures
+                .recover(process_dir(message, &mut buf, options, || {}))?;
         }
         Ok(())
     }
@@ -251,17 +723,33 @@ mod compat {
     fn process_dir(
         node: TreeNode,
         buf: &mut [MaybeUninit<u8>],
+        options: &Options,
         maybe_spawn: impl FnMut(),
     ) -> Result<(), Error> {
-        let dir = openat(
-            CWD,
-            &node.path,
-            OFlags::RDONLY | OFlags::DIRECTORY | OFlags::NOFOLLOW,
-            Mode::empty(),
-        )
-        .map_io_err(|| format!("Failed to open directory: {:?}", node.path))?;
-        let node = delete_dir_contents(node, dir, buf, maybe_spawn)?;
-        delete_dir(node)
+        let open = || {
+            openat(
+                CWD,
+                &node.path,
+                OFlags::RDONLY | OFlags::DIRECTORY | OFlags::NOFOLLOW,
+                Mode::empty(),
+            )
+        };
+        let dir = match open() {
+            Err(Errno::ACCESS) if options.walk.fix_permissions && fix_dir_permissions(&node) => open(),
+            r => r,
+        }
+        .map_io_err(|| format!("Failed to open directory: {:?}", node.path));
+        let Some(dir) = options.failures.recover(dir)? else {
+            node.kept.store(true, Ordering::Relaxed);
+            return delete_dir(Some(node), options);
+        };
+        if !options.predicate.is_empty()
+            && options.failures.recover(dir_matches(&node, &dir, options))? != Some(true)
+        {
+            node.kept.store(true, Ordering::Relaxed);
+        }
+        let node = delete_dir_contents(node, dir, buf, options, maybe_spawn)?;
+        delete_dir(node, options)
     }
 
     #[cfg_attr(
@@ -272,92 +760,296 @@ mod compat {
         node: TreeNode,
         dir: OwnedFd,
         buf: &mut [MaybeUninit<u8>],
+        options: &Options,
         mut maybe_spawn: impl FnMut(),
     ) -> Result<Option<TreeNode>, Error> {
-        enum Arcable<T> {
-            Raw(T),
-            Arced(Arc<T>),
-        }
-
-        impl<T> Arcable<T> {
-            fn into_inner(this: Self) -> Option<T> {
-                match this {
-                    Self::Raw(t) => Some(t),
-                    Self::Arced(arc) => Arc::into_inner(arc),
-                }
-            }
-        }
-
-        impl<T> AsRef<T> for Arcable<T> {
-            fn as_ref(&self) -> &T {
-                match self {
-                    Self::Raw(node) => node,
-                    Self::Arced(arc) => arc,
-                }
-            }
-        }
+        let Some(mount) = options.failures.recover(
+            options
+                .walk
+                .one_file_system
+                .then(|| get_mount(&dir, c"", &node.path))
+                .transpose(),
+        )?
+        else {
+            node.kept.store(true, Ordering::Relaxed);
+            return Ok(Some(node));
+        };
 
         let mut node = Arcable::Raw(node);
         let mut raw_dir = RawDir::new(&dir, buf);
         while let Some(file) = raw_dir.next() {
-            let file =
-                file.map_io_err(|| format!("Failed to read directory: {:?}", node.as_ref().path))?;
-            {
-                let name = file.file_name();
-                if name == c"." || name == c".." {
-                    continue;
-                }
+            options.cancel.check()?;
+            let Some(file) =
+                options.failures.recover(file.map_io_err(|| {
+                    format!("Failed to read directory: {:?}", node.as_ref().path)
+                }))?
+            else {
+                node.as_ref().kept.store(true, Ordering::Relaxed);
+                break;
+            };
+            let name = file.file_name();
+            if name == c"." || name == c".." {
+                continue;
             }
 
             let file_type = match file.file_type() {
-                FileType::Unknown => get_file_type(&dir, file.file_name(), &node.as_ref().path)?,
+                FileType::Unknown => {
+                    let Some(file_type) = options.failures.recover(get_file_type(
+                        &dir,
+                        name,
+                        &node.as_ref().path,
+                    ))?
+                    else {
+                        node.as_ref().kept.store(true, Ordering::Relaxed);
+                        continue;
+                    };
+                    file_type
+                }
                 t => t,
             };
-            if file_type == FileType::Directory {
-                if node.as_ref().path.as_bytes_with_nul().len() + file.file_name().count_bytes()
-                    > 4096
-                {
-                    long_path_fallback_deletion(&node.as_ref().path, file.file_name())?;
-                    continue;
+            if file_type != FileType::Directory {
+                delete_entry(node.as_ref(), &dir, name, file_type, options)?;
+                continue;
+            }
+            if !descend(node.as_ref(), &dir, name, mount, options)? {
+                continue;
+            }
+
+            maybe_spawn();
+
+            let node = match node {
+                Arcable::Raw(raw) => {
+                    let arc = Arc::new(raw);
+                    node = Arcable::Arced(arc.clone());
+                    arc
                 }
+                Arcable::Arced(ref node) => node.clone(),
+            };
+            node.messages
+                .send(TreeNode {
+                    path: concat_cstrs(&node.path, name),
+                    parent: Some(node.clone()),
+                    messages: node.messages.clone(),
+                    kept: AtomicBool::new(false),
+                })
+                .map_err(|_| Error::Internal)?;
+        }
 
-                maybe_spawn();
+        Ok(Arcable::into_inner(node))
+    }
 
-                let node = match node {
-                    Arcable::Raw(raw) => {
-                        let arc = Arc::new(raw);
-                        node = Arcable::Arced(arc.clone());
-                        arc
-                    }
-                    Arcable::Arced(ref node) => node.clone(),
-                };
-                node.messages
-                    .send(TreeNode {
-                        path: concat_cstrs(&node.path, file.file_name()),
-                        parent: Some(node.clone()),
-                        messages: node.messages.clone(),
-                    })
-                    .map_err(|_| Error::Internal)?;
-            } else {
-                delete_file(node.as_ref(), &dir, file.file_name())?;
+    /// Returns whether one of the directory's subdirectories should be handed
+    /// to a worker to empty, deleting it right away if its path is too long.
+    fn descend(
+        node: &TreeNode,
+        dir: impl AsFd,
+        name: &CStr,
+        mount: Option<(u64, u64)>,
+        options: &Options,
+    ) -> Result<bool, Error> {
+        if let Some(mount) = mount {
+            let Some(file_mount) = options
+                .failures
+                .recover(get_mount(&dir, name, &node.path))?
+            else {
+                node.kept.store(true, Ordering::Relaxed);
+                return Ok(false);
+            };
+            if file_mount != mount {
+                return Ok(false);
             }
         }
+        if !confirm_entry(node, name, true, options) {
+            return Ok(false);
+        }
+        if node.path.as_bytes_with_nul().len() + name.count_bytes() > 4096 {
+            delete_long_subdir(node, name, options)?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
 
-        Ok(Arcable::into_inner(node))
+    /// Deletes a subdirectory whose path is too long to open directly, unless
+    /// it's meant to be searched for matching files.
+    #[cold]
+    fn delete_long_subdir(node: &TreeNode, name: &CStr, options: &Options) -> Result<(), Error> {
+        if !options.predicate.is_empty() {
+            node.kept.store(true, Ordering::Relaxed);
+            options.failures.recover(Err::<(), _>(Error::Io {
+                error: Errno::NAMETOOLONG.into(),
+                context: format!(
+                    "Too deep to search for matching files: {:?}",
+                    join_cstr_paths(&node.path, name)
+                )
+                .into(),
+            }))?;
+            return Ok(());
+        }
+        if options.dry_run {
+            options.progress.planned(&join_cstr_paths(&node.path, name));
+        } else if options
+            .failures
+            .recover(long_path_fallback_deletion(&node.path, name))?
+            .is_none()
+        {
+            node.kept.store(true, Ordering::Relaxed);
+            return Ok(());
+        }
+        options.progress.dir_done();
+        Ok(())
+    }
+
+    /// Deletes one of the directory's non-directory entries if it matches the
+    /// predicate and the removal is confirmed.
+    fn delete_entry(
+        node: &TreeNode,
+        dir: impl AsFd,
+        name: &CStr,
+        file_type: FileType,
+        options: &Options,
+    ) -> Result<(), Error> {
+        if !options.predicate.is_empty()
+            && options.failures.recover(options.predicate.matches(
+                OsStr::from_bytes(name.to_bytes()),
+                file_kind(file_type),
+                || stats(&dir, name, &node.path),
+            ))? != Some(true)
+        {
+            node.kept.store(true, Ordering::Relaxed);
+            return Ok(());
+        }
+        if !confirm_entry(node, name, false, options) {
+            return Ok(());
+        }
+        if options.dry_run {
+            options.progress.planned(&join_cstr_paths(&node.path, name));
+        } else if options
+            .failures
+            .recover(delete_file(node, &dir, name, options))?
+            .is_none()
+        {
+            node.kept.store(true, Ordering::Relaxed);
+            return Ok(());
+        }
+        options.progress.file_done(0);
+        Ok(())
+    }
+
+    fn dir_matches(node: &TreeNode, dir: impl AsFd, options: &Options) -> Result<bool, Error> {
+        let path = Path::new(OsStr::from_bytes(node.path.as_bytes()));
+        options.predicate.matches(
+            path.file_name().unwrap_or(path.as_os_str()),
+            FileKind::Directory,
+            || stats(&dir, c"", &node.path),
+        )
+    }
+
+    const fn file_kind(file_type: FileType) -> FileKind {
+        match file_type {
+            FileType::RegularFile => FileKind::File,
+            FileType::Directory => FileKind::Directory,
+            FileType::Symlink => FileKind::Symlink,
+            _ => FileKind::Other,
+        }
+    }
+
+    /// Reads the metadata of the directory's entry, or of the directory itself
+    /// if the name is empty.
+    fn stats(dir: impl AsFd, name: &CStr, path: &CString) -> Result<Stats, Error> {
+        let time = |timestamp: StatxTimestamp| {
+            let secs = Duration::from_secs(timestamp.tv_sec.unsigned_abs());
+            let whole = if timestamp.tv_sec < 0 {
+                UNIX_EPOCH - secs
+            } else {
+                UNIX_EPOCH + secs
+            };
+            whole + Duration::from_nanos(timestamp.tv_nsec.into())
+        };
+
+        let statx = statx(
+            dir,
+            name,
+            AtFlags::SYMLINK_NOFOLLOW | AtFlags::EMPTY_PATH,
+            StatxFlags::MTIME | StatxFlags::ATIME | StatxFlags::SIZE,
+        )
+        .map_io_err(|| {
+            format!(
+                "Failed to read metadata for file: {:?}",
+                join_cstr_paths(path, name)
+            )
+        })?;
+        Ok(Stats {
+            modified: time(statx.stx_mtime),
+            accessed: time(statx.stx_atime),
+            size: statx.stx_size,
+        })
     }
 
-    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(node)))]
-    fn delete_dir(mut node: Option<TreeNode>) -> Result<(), Error> {
+    /// Asks whether a directory entry may be removed, remembering a refusal so
+    /// its parent directories are kept.
+    fn confirm_entry(node: &TreeNode, file: &CStr, is_dir: bool, options: &Options) -> bool {
+        if !options.confirm_entries
+            || options
+                .confirm
+                .confirm_entry(&join_cstr_paths(&node.path, file), is_dir)
+        {
+            return true;
+        }
+        node.kept.store(true, Ordering::Relaxed);
+        false
+    }
+
+    /// Asks whether an emptied directory may be removed, the way `rm -i` does
+    /// after descending into it.
+    fn confirm_emptied(path: &CStr, options: &Options) -> bool {
+        !options.confirm_entries
+            || options
+                .confirm
+                .confirm_emptied(Path::new(OsStr::from_bytes(path.to_bytes())))
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(node, options))
+    )]
+    fn delete_dir(mut node: Option<TreeNode>, options: &Options) -> Result<(), Error> {
         let mut result = Ok(());
         while let Some(TreeNode {
             ref path,
             parent,
             messages: _,
+            kept,
         }) = node
         {
-            if result.is_ok() {
-                result = unlinkat(CWD, path, AtFlags::REMOVEDIR)
+            if kept.into_inner() || result.is_err() || !confirm_emptied(path, options) {
+                if let Some(parent) = &parent {
+                    parent.kept.store(true, Ordering::Relaxed);
+                }
+            } else {
+                if options.dry_run {
+                    options
+                        .progress
+                        .planned(Path::new(OsStr::from_bytes(path.as_bytes())));
+                } else {
+                    let remove = || unlinkat(CWD, path, AtFlags::REMOVEDIR);
+                    result = match remove() {
+                        Err(Errno::ACCESS | Errno::PERM)
+                            if options.walk.fix_permissions
+                                && grant_parent_permissions(Path::new(OsStr::from_bytes(
+                                    path.as_bytes(),
+                                ))) =>
+                        {
+                            remove()
+                        }
+                        r => r,
+                    }
                     .map_io_err(|| format!("Failed to delete directory: {path:?}"));
+                }
+                match (&result, &parent) {
+                    (Ok(()), _) => options.progress.dir_done(),
+                    (Err(_), Some(parent)) => parent.kept.store(true, Ordering::Relaxed),
+                    (Err(_), None) => {}
+                }
             }
             node = parent.and_then(Arc::into_inner);
         }
@@ -368,8 +1060,22 @@ mod compat {
         feature = "tracing",
         tracing::instrument(level = "trace", skip(node, dir))
     )]
-    fn delete_file(node: &TreeNode, dir: impl AsFd, file: &CStr) -> Result<(), Error> {
-        unlinkat(&dir, file, AtFlags::empty()).map_io_err(|| {
+    fn delete_file(
+        node: &TreeNode,
+        dir: impl AsFd,
+        file: &CStr,
+        options: &Options,
+    ) -> Result<(), Error> {
+        let remove = || unlinkat(&dir, file, AtFlags::empty());
+        match remove() {
+            Err(Errno::ACCESS | Errno::PERM)
+                if options.walk.fix_permissions && grant_permissions(&dir, 0o300) =>
+            {
+                remove()
+            }
+            r => r,
+        }
+        .map_io_err(|| {
             format!(
                 "Failed to delete file: {:?}",
                 join_cstr_paths(&node.path, file)
@@ -377,6 +1083,60 @@ mod compat {
         })
     }
 
+    /// Makes the directory listable, along with its parent in case that's
+    /// what's keeping it from being opened.
+    #[cold]
+    fn fix_dir_permissions(node: &TreeNode) -> bool {
+        let parent_fixed =
+            grant_parent_permissions(Path::new(OsStr::from_bytes(node.path.as_bytes())));
+        open_path(&node.path).is_some_and(|dir| grant_permissions(dir, 0o700)) || parent_fixed
+    }
+
+    /// Gives the owner of the directory holding the file write and search
+    /// permission, returning whether anything changed.
+    #[cold]
+    pub fn grant_parent_permissions(path: &Path) -> bool {
+        parent_dir(path)
+            .and_then(open_path)
+            .is_some_and(|dir| grant_permissions(dir, 0o300))
+    }
+
+    /// Opens the directory just to change it, which works even without read
+    /// permission and can't be redirected by a symlink.
+    fn open_path(dir: impl Arg) -> Option<OwnedFd> {
+        openat(
+            CWD,
+            dir,
+            OFlags::PATH | OFlags::DIRECTORY | OFlags::NOFOLLOW,
+            Mode::empty(),
+        )
+        .ok()
+    }
+
+    /// Gives the directory's owner the `missing` permissions, returning whether
+    /// anything changed.
+    #[cold]
+    fn grant_permissions(dir: impl AsFd, missing: RawMode) -> bool {
+        let Ok(stat) = fstat(&dir) else {
+            return false;
+        };
+        let mode = stat.st_mode & 0o7777;
+        if mode | missing == mode {
+            return false;
+        }
+        let mode = Mode::from_raw_mode(mode | missing);
+        match fchmod(&dir, mode) {
+            // Directories opened with `O_PATH` can only be changed through their
+            // procfs link.
+            Err(Errno::BADF) => chmod(
+                format!("/proc/thread-self/fd/{}", dir.as_fd().as_raw_fd()),
+                mode,
+            )
+            .is_ok(),
+            r => r.is_ok(),
+        }
+    }
+
     #[cold]
     #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace"))]
     fn long_path_fallback_deletion(parent: &CString, child: &CStr) -> Result<(), Error> {
@@ -410,33 +1170,75 @@ mod compat {
         Ok(())
     }
 
+    /// A directory's node, which only needs sharing once its subdirectories
+    /// are handed to other workers.
+    enum Arcable<T> {
+        Raw(T),
+        Arced(Arc<T>),
+    }
+
+    impl<T> Arcable<T> {
+        fn into_inner(this: Self) -> Option<T> {
+            match this {
+                Self::Raw(t) => Some(t),
+                Self::Arced(arc) => Arc::into_inner(arc),
+            }
+        }
+    }
+
+    impl<T> AsRef<T> for Arcable<T> {
+        fn as_ref(&self) -> &T {
+            match self {
+                Self::Raw(node) => node,
+                Self::Arced(arc) => arc,
+            }
+        }
+    }
+
     struct TreeNode {
         path: CString,
-        parent: Option<Arc<TreeNode>>,
-        messages: Sender<TreeNode>,
+        parent: Option<Arc<Self>>,
+        messages: Sender<Self>,
+        /// Set once something inside the directory was declined, or from the
+        /// start if only the directory's contents are being removed.
+        kept: AtomicBool,
     }
 }
 
 #[cfg(all(not(target_os = "linux"), not(target_os = "windows")))]
 mod compat {
-    use std::{borrow::Cow, fs, io, path::Path};
+    use std::{
+        borrow::Cow,
+        fs::{self, DirEntry},
+        io,
+        os::unix::fs::PermissionsExt,
+        path::Path,
+        sync::atomic::{AtomicBool, Ordering},
+    };
 
     use rayon::prelude::*;
 
     use crate::{
-        ops::{compat::DirectoryOp, IoErr},
+        ops::{
+            compat::DirectoryOp,
+            predicate::{FileKind, Stats},
+            remove::{parent_dir, Options},
+            IoErr,
+        },
         Error,
     };
 
-    struct Impl;
+    struct Impl {
+        options: Options,
+    }
 
-    pub fn remove_impl<'a>() -> impl DirectoryOp<Cow<'a, Path>> {
-        Impl
+    pub fn remove_impl<'a>(options: Options) -> impl DirectoryOp<Cow<'a, Path>> {
+        Impl { options }
     }
 
     impl DirectoryOp<Cow<'_, Path>> for Impl {
         fn run(&self, dir: Cow<Path>) -> Result<(), Error> {
-            remove_dir_all(&dir).map_io_err(|| format!("Failed to delete directory: {dir:?}"))
+            remove_dir_all(&dir, None, &self.options).map(|_| ())
         }
 
         fn finish(self) -> Result<(), Error> {
@@ -444,47 +1246,202 @@ mod compat {
         }
     }
 
-    fn remove_dir_all<P: AsRef<Path>>(path: P) -> Result<(), io::Error> {
-        let path = path.as_ref();
-        path.read_dir()?
-            .par_bridge()
-            .try_for_each(|dir_entry| -> io::Result<()> {
-                let dir_entry = dir_entry?;
-                if dir_entry.file_type()?.is_dir() {
-                    remove_dir_all(dir_entry.path())?;
+    /// Returns whether the directory was removed rather than kept because
+    /// something inside it was declined or only its contents were wanted. The
+    /// parent is only given for directories inside the ones being removed.
+    fn remove_dir_all(
+        path: &Path,
+        parent: Option<&Path>,
+        options: &Options,
+    ) -> Result<bool, Error> {
+        let kept = AtomicBool::new(parent.is_none() && options.walk.contents_only);
+        let entries = match path.read_dir() {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied && options.walk.fix_permissions => {
+                // The parent may be what's keeping the directory from being opened.
+                let parent_fixed = parent.is_some_and(|parent| grant_permissions(parent, 0o300));
+                if grant_permissions(path, 0o700) || parent_fixed {
+                    path.read_dir()
                 } else {
-                    fs::remove_file(dir_entry.path())?;
+                    Err(e)
+                }
+            }
+            r => r,
+        }
+        .map_io_err(|| format!("Failed to read directory: {path:?}"))?;
+        if !options.predicate.is_empty()
+            && !options.predicate.matches(
+                path.file_name().unwrap_or(path.as_os_str()),
+                FileKind::Directory,
+                || stats(path),
+            )?
+        {
+            kept.store(true, Ordering::Relaxed);
+        }
+        entries
+            .par_bridge()
+            .try_for_each(|dir_entry| -> Result<(), Error> {
+                if options
+                    .failures
+                    .recover(remove_entry(path, dir_entry, options))?
+                    != Some(true)
+                {
+                    kept.store(true, Ordering::Relaxed);
                 }
                 Ok(())
             })?;
-        fs::remove_dir(path)
+        if kept.into_inner()
+            || (options.confirm_entries && !options.confirm.confirm_emptied(path))
+        {
+            return Ok(false);
+        }
+        if options.dry_run {
+            options.progress.planned(path);
+        } else {
+            retry_with_permissions(path, options, || fs::remove_dir(path))
+                .map_io_err(|| format!("Failed to delete directory: {path:?}"))?;
+        }
+        options.progress.dir_done();
+        Ok(true)
+    }
+
+    fn remove_entry(
+        dir: &Path,
+        dir_entry: io::Result<DirEntry>,
+        options: &Options,
+    ) -> Result<bool, Error> {
+        options.cancel.check()?;
+        let dir_entry = dir_entry.map_io_err(|| format!("Failed to read directory: {dir:?}"))?;
+        let path = dir_entry.path();
+        let file_type = dir_entry
+            .file_type()
+            .map_io_err(|| format!("Failed to read metadata for file: {path:?}"))?;
+        let is_dir = file_type.is_dir();
+        if !is_dir
+            && !options.predicate.is_empty()
+            && !options.predicate.matches(
+                &dir_entry.file_name(),
+                FileKind::from_file_type(file_type),
+                || stats(&path),
+            )?
+        {
+            return Ok(false);
+        }
+        if options.confirm_entries && !options.confirm.confirm_entry(&path, is_dir) {
+            return Ok(false);
+        }
+        if is_dir {
+            return remove_dir_all(&path, Some(dir), options);
+        }
+
+        if options.dry_run {
+            options.progress.planned(&path);
+        } else {
+            retry_with_permissions(&path, options, || fs::remove_file(&path))
+                .map_io_err(|| format!("Failed to delete file: {path:?}"))?;
+        }
+        options.progress.file_done(0);
+        Ok(true)
+    }
+
+    fn stats(path: &Path) -> Result<Stats, Error> {
+        path.symlink_metadata()
+            .and_then(|metadata| Stats::from_metadata(&metadata))
+            .map_io_err(|| format!("Failed to read metadata for file: {path:?}"))
+    }
+
+    /// Deletes the file, making the directory holding it writable and trying
+    /// again if permissions got in the way.
+    fn retry_with_permissions(
+        path: &Path,
+        options: &Options,
+        remove: impl Fn() -> io::Result<()>,
+    ) -> io::Result<()> {
+        match remove() {
+            Err(e)
+                if e.kind() == io::ErrorKind::PermissionDenied
+                    && options.walk.fix_permissions
+                    && grant_parent_permissions(path) =>
+            {
+                remove()
+            }
+            r => r,
+        }
+    }
+
+    /// Gives the owner of the directory holding the file write and search
+    /// permission, returning whether anything changed.
+    #[cold]
+    pub fn grant_parent_permissions(path: &Path) -> bool {
+        parent_dir(path).is_some_and(|dir| grant_permissions(dir, 0o300))
+    }
+
+    /// Gives the directory's owner the `missing` permissions, returning whether
+    /// anything changed.
+    #[cold]
+    fn grant_permissions(dir: &Path, missing: u32) -> bool {
+        let Ok(metadata) = dir.symlink_metadata() else {
+            return false;
+        };
+        let mode = metadata.permissions().mode() & 0o7777;
+        mode | missing != mode
+            && fs::set_permissions(dir, fs::Permissions::from_mode(mode | missing)).is_ok()
     }
 }
 
 #[cfg(target_os = "windows")]
 mod compat {
-    use std::{borrow::Cow, path::Path};
+    use std::{borrow::Cow, io, path::Path};
 
-    use remove_dir_all::remove_dir_all;
+    use remove_dir_all::{remove_dir_all, remove_dir_contents};
 
     use crate::{
-        ops::{compat::DirectoryOp, IoErr},
+        ops::{compat::DirectoryOp, remove::Options, IoErr},
         Error,
     };
 
-    struct Impl;
+    struct Impl {
+        options: Options,
+    }
 
-    pub fn remove_impl<'a>() -> impl DirectoryOp<Cow<'a, Path>> {
-        Impl
+    pub fn remove_impl<'a>(options: Options) -> impl DirectoryOp<Cow<'a, Path>> {
+        Impl { options }
     }
 
     impl DirectoryOp<Cow<'_, Path>> for Impl {
         fn run(&self, dir: Cow<Path>) -> Result<(), Error> {
-            remove_dir_all(&dir).map_io_err(|| format!("Failed to delete directory: {dir:?}"))
+            self.options.cancel.check()?;
+            if !self.options.predicate.is_empty() {
+                return Err(Error::Io {
+                    error: io::ErrorKind::Unsupported.into(),
+                    context: format!("Cannot search directory for matching files: {dir:?}").into(),
+                });
+            }
+            // The directory's contents are out of sight here, so dry runs can only
+            // report the directory itself.
+            if self.options.walk.contents_only {
+                if !self.options.dry_run {
+                    remove_dir_contents(&dir).map_io_err(|| {
+                        format!("Failed to delete the contents of directory: {dir:?}")
+                    })?;
+                }
+                return Ok(());
+            }
+            if self.options.dry_run {
+                self.options.progress.planned(&dir);
+            } else {
+                remove_dir_all(&dir)
+                    .map_io_err(|| format!("Failed to delete directory: {dir:?}"))?;
+            }
+            self.options.progress.dir_done();
+            Ok(())
         }
 
         fn finish(self) -> Result<(), Error> {
             Ok(())
         }
     }
+
+    pub const fn grant_parent_permissions(_: &Path) -> bool {
+        false
+    }
 }
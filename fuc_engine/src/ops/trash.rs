@@ -0,0 +1,220 @@
+use std::path::Path;
+
+use crate::{ops::IoErr, Error};
+
+/// Moves a file or directory into the trash of the filesystem it lives on,
+/// returning `false` without touching it if there's no trash it could be
+/// moved into without copying it.
+#[cfg_attr(feature = "tracing", tracing::instrument(level = "trace"))]
+pub fn trash(path: &Path) -> Result<bool, Error> {
+    compat::trash(path).map_io_err(|| format!("Failed to move file to the trash: {path:?}"))
+}
+
+/// Implements the [freedesktop.org trash specification](https://specifications.freedesktop.org/trash-spec/trashspec-latest.html).
+#[cfg(target_os = "linux")]
+mod compat {
+    use std::{
+        env,
+        fmt::Write as _,
+        fs::{self, File},
+        io::{self, Write},
+        mem,
+        os::unix::{
+            ffi::OsStrExt,
+            fs::{DirBuilderExt, MetadataExt, OpenOptionsExt},
+        },
+        path::{Path, PathBuf},
+        ptr,
+    };
+
+    use rustix::fs::{renameat_with, statx, AtFlags, RenameFlags, StatxFlags, CWD};
+
+    pub fn trash(path: &Path) -> io::Result<bool> {
+        let dev = path.symlink_metadata()?.dev();
+        // The original location is recorded with its parent's symlinks resolved, but
+        // not the file's own name since that's what's being trashed.
+        let absolute = {
+            let name = path.file_name().ok_or(io::ErrorKind::InvalidInput)?;
+            match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+                _ => env::current_dir()?,
+            }
+            .join(name)
+        };
+        // Sharing a device doesn't guarantee sharing a mount, in which case the
+        // home trash can't be renamed into after all.
+        if let Some(trash) = home_trash(dev)? {
+            if trash_into(&trash, path, &absolute)? {
+                return Ok(true);
+            }
+        }
+        let Some(trash) = top_dir_trash(path, &absolute)? else {
+            return Ok(false);
+        };
+        trash_into(&trash, path, &absolute)
+    }
+
+    /// Returns `false` if the trash is on a different mount than the file.
+    fn trash_into(trash: &Path, path: &Path, absolute: &Path) -> io::Result<bool> {
+        let files = trash.join("files");
+        let info = trash.join("info");
+        create_private_dir(&files)?;
+        create_private_dir(&info)?;
+
+        let name = absolute.file_name().ok_or(io::ErrorKind::InvalidInput)?;
+        for n in 1u64.. {
+            let mut trashed = name.to_os_string();
+            if n > 1 {
+                trashed.push(format!(".{n}"));
+            }
+            let info_path = {
+                let mut info_name = trashed.clone();
+                info_name.push(".trashinfo");
+                info.join(info_name)
+            };
+
+            // Creating the info file first claims the name.
+            let mut info_file = match File::options()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&info_path)
+            {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                r => r?,
+            };
+            let result = info_file
+                .write_all(trash_info(absolute).as_bytes())
+                .and_then(|()| {
+                    renameat_with(CWD, path, CWD, files.join(&trashed), RenameFlags::NOREPLACE)
+                        .map_err(io::Error::from)
+                });
+            match result {
+                Ok(()) => return Ok(true),
+                Err(e) => {
+                    let _ = fs::remove_file(&info_path);
+                    match e.kind() {
+                        // A file was left behind without its info file.
+                        io::ErrorKind::AlreadyExists => {}
+                        io::ErrorKind::CrossesDevices => return Ok(false),
+                        _ => return Err(e),
+                    }
+                }
+            }
+        }
+        unreachable!()
+    }
+
+    /// Returns the home trash if it's on the same filesystem as the file.
+    fn home_trash(dev: u64) -> io::Result<Option<PathBuf>> {
+        let Some(data_home) = env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|data_home| data_home.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+        else {
+            return Ok(None);
+        };
+        let home_trash = data_home.join("Trash");
+        let same_filesystem = home_trash
+            .ancestors()
+            .find_map(|dir| dir.metadata().ok())
+            .is_some_and(|metadata| metadata.dev() == dev);
+        if !same_filesystem {
+            return Ok(None);
+        }
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&home_trash)?;
+        Ok(Some(home_trash))
+    }
+
+    /// Returns the trash at the top of the file's mount.
+    fn top_dir_trash(path: &Path, absolute: &Path) -> io::Result<Option<PathBuf>> {
+        let mount_id = |path: &Path, flags| {
+            statx(CWD, path, flags, StatxFlags::MNT_ID).map(|metadata| metadata.stx_mnt_id)
+        };
+        let mount = mount_id(path, AtFlags::SYMLINK_NOFOLLOW)?;
+        let Some(top_dir) = absolute
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| mount_id(dir, AtFlags::empty()).is_ok_and(|id| id == mount))
+            .last()
+        else {
+            return Ok(None);
+        };
+        let uid = unsafe { libc::getuid() };
+
+        // Administrators may provide a shared trash, which must be sticky to keep
+        // users out of each other's trash.
+        let shared = top_dir.join(".Trash");
+        if let Ok(metadata) = shared.symlink_metadata() {
+            if metadata.is_dir() && metadata.mode() & 0o1000 != 0 {
+                let trash = shared.join(uid.to_string());
+                if create_user_dir(&trash, uid) {
+                    return Ok(Some(trash));
+                }
+            }
+        }
+        let trash = top_dir.join(format!(".Trash-{uid}"));
+        Ok(create_user_dir(&trash, uid).then_some(trash))
+    }
+
+    /// Creates a directory only the user can access, returning `false` if one
+    /// already exists but belongs to someone else or is open to others.
+    fn create_user_dir(dir: &Path, uid: u32) -> bool {
+        create_private_dir(dir).is_ok()
+            && dir.symlink_metadata().is_ok_and(|metadata| {
+                metadata.is_dir() && metadata.uid() == uid && metadata.mode() & 0o777 == 0o700
+            })
+    }
+
+    fn create_private_dir(dir: &Path) -> io::Result<()> {
+        match fs::DirBuilder::new().mode(0o700).create(dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                if dir.symlink_metadata()?.is_dir() {
+                    Ok(())
+                } else {
+                    Err(e)
+                }
+            }
+            r => r,
+        }
+    }
+
+    fn trash_info(absolute: &Path) -> String {
+        let mut path = String::new();
+        for &b in absolute.as_os_str().as_bytes() {
+            if b.is_ascii_alphanumeric() || b"/-_.!~*'()".contains(&b) {
+                path.push(char::from(b));
+            } else {
+                let _ = write!(path, "%{b:02X}");
+            }
+        }
+
+        let tm = unsafe {
+            let now = libc::time(ptr::null_mut());
+            let mut tm = mem::zeroed::<libc::tm>();
+            libc::localtime_r(ptr::from_ref(&now), ptr::from_mut(&mut tm));
+            tm
+        };
+        format!(
+            "[Trash Info]\nPath={path}\nDeletionDate={:04}-{:02}-{:02}T{:02}:{:02}:{:02}\n",
+            tm.tm_year + 1900,
+            tm.tm_mon + 1,
+            tm.tm_mday,
+            tm.tm_hour,
+            tm.tm_min,
+            tm.tm_sec,
+        )
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod compat {
+    use std::{io, path::Path};
+
+    pub fn trash(_: &Path) -> io::Result<bool> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread::{self, ThreadId},
+};
+
+#[cfg(target_os = "linux")]
+pub use mount::Mount;
+
+/// Records what an operation reports through its [`fuc_engine::Progress`].
+#[derive(Debug, Default)]
+pub struct Recorder {
+    planned: Mutex<Vec<PathBuf>>,
+    threads: Mutex<HashSet<ThreadId>>,
+}
+
+impl Recorder {
+    /// The paths reported by dry runs, in the order they were reported.
+    pub fn planned_paths(&self) -> Vec<PathBuf> {
+        self.planned.lock().unwrap().clone()
+    }
+
+    /// How many distinct threads finished files or directories.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub fn thread_count(&self) -> usize {
+        self.threads.lock().unwrap().len()
+    }
+
+    fn record_thread(&self) {
+        self.threads.lock().unwrap().insert(thread::current().id());
+    }
+}
+
+impl fuc_engine::Progress for Recorder {
+    fn planned(&self, path: &Path) {
+        self.planned.lock().unwrap().push(path.to_path_buf());
+    }
+
+    fn file_done(&self, _: u64) {
+        self.record_thread();
+    }
+
+    fn dir_done(&self) {
+        self.record_thread();
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod mount {
+    use std::{
+        ffi::OsStr,
+        fs,
+        path::{Path, PathBuf},
+        process::{Command, Stdio},
+    };
+
+    /// A mount that lasts for as long as this lives.
+    pub struct Mount(PathBuf);
+
+    impl Mount {
+        /// Mounts a new tmpfs at the given directory, creating it first.
+        /// Returns `None` when mounting isn't permitted, in which case the test
+        /// should be skipped.
+        pub fn tmpfs(path: &Path) -> Option<Self> {
+            Self::run(["-t", "tmpfs", "tmpfs"].map(OsStr::new), path)
+        }
+
+        /// Makes `source` also available at the given directory, creating it
+        /// first. The result lives on the same device but a different mount.
+        #[allow(dead_code)]
+        pub fn bind(source: &Path, path: &Path) -> Option<Self> {
+            Self::run([OsStr::new("--bind"), source.as_os_str()], path)
+        }
+
+        /// Makes the mount read-only so nothing inside it can be deleted.
+        #[allow(dead_code)]
+        pub fn remount_read_only(&self) {
+            let status = Command::new("mount")
+                .args(["-o", "remount,ro"])
+                .arg(&self.0)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+
+        fn run<const N: usize>(args: [&OsStr; N], path: &Path) -> Option<Self> {
+            fs::create_dir_all(path).unwrap();
+            let mounted = Command::new("mount")
+                .args(args)
+                .arg(path)
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success());
+            if mounted {
+                Some(Self(path.to_path_buf()))
+            } else {
+                eprintln!("Skipping test: unable to mount {path:?}");
+                None
+            }
+        }
+    }
+
+    impl Drop for Mount {
+        fn drop(&mut self) {
+            let _ = Command::new("umount").arg("--lazy").arg(&self.0).status();
+        }
+    }
+}
@@ -1,7 +1,14 @@
-use std::{borrow::Cow, fs, fs::File};
+use std::{
+    borrow::Cow,
+    fs,
+    fs::File,
+    path::{Path, PathBuf},
+};
 
 use tempfile::tempdir;
 
+mod common;
+
 #[test]
 fn pre_existing_file_no_force() {
     let root = tempdir().unwrap();
@@ -144,3 +151,901 @@ fn symbolic_link_copy_link() {
 
     assert!(to.exists());
 }
+
+#[test]
+#[cfg(target_os = "linux")]
+fn preserve_metadata() {
+    use std::{
+        fs::{FileTimes, Permissions},
+        os::unix::fs::{MetadataExt, PermissionsExt},
+        time::{Duration, SystemTime},
+    };
+
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir(&from).unwrap();
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    {
+        let file = File::create(from.join("file")).unwrap();
+        file.set_times(FileTimes::new().set_accessed(time).set_modified(time))
+            .unwrap();
+        file.set_permissions(Permissions::from_mode(0o741)).unwrap();
+    }
+    fs::create_dir(from.join("dir")).unwrap();
+    File::create(from.join("dir/file")).unwrap();
+    File::open(from.join("dir"))
+        .unwrap()
+        .set_modified(time)
+        .unwrap();
+    fs::set_permissions(from.join("dir"), Permissions::from_mode(0o555)).unwrap();
+    let to = root.path().join("to");
+
+    fuc_engine::CopyOp::builder()
+        .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+        .preserve(fuc_engine::Preserve {
+            mode: true,
+            ownership: true,
+            timestamps: true,
+            ..Default::default()
+        })
+        .build()
+        .run()
+        .unwrap();
+
+    for file in ["file", "dir", "dir/file"] {
+        let from = fs::symlink_metadata(from.join(file)).unwrap();
+        let to = fs::symlink_metadata(to.join(file)).unwrap();
+        assert_eq!(from.mode(), to.mode(), "{file}");
+        assert_eq!(from.uid(), to.uid(), "{file}");
+        assert_eq!(from.gid(), to.gid(), "{file}");
+        assert_eq!(from.modified().unwrap(), to.modified().unwrap(), "{file}");
+    }
+    assert_eq!(
+        fs::symlink_metadata(to.join("file"))
+            .unwrap()
+            .accessed()
+            .unwrap(),
+        time
+    );
+    fs::set_permissions(from.join("dir"), Permissions::from_mode(0o755)).unwrap();
+    fs::set_permissions(to.join("dir"), Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn preserve_single_file_timestamps() {
+    use std::time::{Duration, SystemTime};
+
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    File::create(&from).unwrap().set_modified(time).unwrap();
+    let to = root.path().join("to");
+
+    fuc_engine::CopyOp::builder()
+        .files([(Cow::Owned(from), Cow::Borrowed(to.as_path()))])
+        .preserve(fuc_engine::Preserve {
+            timestamps: true,
+            ..Default::default()
+        })
+        .build()
+        .run()
+        .unwrap();
+
+    assert_eq!(fs::metadata(&to).unwrap().modified().unwrap(), time);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn preserve_xattrs() {
+    use rustix::{
+        fs::{getxattr, setxattr, XattrFlags},
+        io::Errno,
+    };
+
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir(&from).unwrap();
+    File::create(from.join("file")).unwrap();
+    match setxattr(&from, "user.fuc", b"dir", XattrFlags::empty()) {
+        // The filesystem doesn't support user xattrs, so there's nothing to test.
+        Err(Errno::NOTSUP) => return,
+        r => r.unwrap(),
+    }
+    setxattr(from.join("file"), "user.fuc", b"file", XattrFlags::empty()).unwrap();
+    let to = root.path().join("to");
+
+    fuc_engine::CopyOp::builder()
+        .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+        .preserve(fuc_engine::Preserve {
+            xattr: true,
+            ..Default::default()
+        })
+        .build()
+        .run()
+        .unwrap();
+
+    let mut value = [0; 16];
+    let len = getxattr(&to, "user.fuc", &mut value).unwrap();
+    assert_eq!(&value[..len], b"dir");
+    let len = getxattr(to.join("file"), "user.fuc", &mut value).unwrap();
+    assert_eq!(&value[..len], b"file");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn hard_links() {
+    use std::os::unix::fs::MetadataExt;
+
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir_all(from.join("nested")).unwrap();
+    fs::write(from.join("a"), "a").unwrap();
+    fs::hard_link(from.join("a"), from.join("b")).unwrap();
+    fs::hard_link(from.join("a"), from.join("nested/c")).unwrap();
+    fs::write(from.join("d"), "d").unwrap();
+
+    for atomic in [fuc_engine::Atomic::Never, fuc_engine::Atomic::Files] {
+        let to = root.path().join(format!("{atomic:?}"));
+
+        fuc_engine::CopyOp::builder()
+            .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+            .atomic(atomic)
+            .build()
+            .run()
+            .unwrap();
+
+        let a = fs::metadata(to.join("a")).unwrap();
+        assert_eq!(a.nlink(), 3);
+        assert_eq!(fs::metadata(to.join("b")).unwrap().ino(), a.ino());
+        assert_eq!(fs::metadata(to.join("nested/c")).unwrap().ino(), a.ino());
+        assert_eq!(fs::read_to_string(to.join("nested/c")).unwrap(), "a");
+        assert_eq!(fs::metadata(to.join("d")).unwrap().nlink(), 1);
+
+        // Links between separate arguments are kept too.
+        let to = root.path().join(format!("{atomic:?}-files"));
+        fuc_engine::CopyOp::builder()
+            .files(["a", "b"].map(|name| (from.join(name), to.join(name))))
+            .atomic(atomic)
+            .build()
+            .run()
+            .unwrap();
+
+        let a = fs::metadata(to.join("a")).unwrap();
+        assert_eq!(a.nlink(), 2);
+        assert_eq!(fs::metadata(to.join("b")).unwrap().ino(), a.ino());
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn reflink() {
+    use fuc_engine::Reflink;
+
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::write(&from, "data").unwrap();
+
+    for (i, reflink) in [Reflink::Auto, Reflink::Always, Reflink::Never]
+        .into_iter()
+        .enumerate()
+    {
+        let to = root.path().join(i.to_string());
+        let result = fuc_engine::CopyOp::builder()
+            .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+            .reflink(reflink)
+            .build()
+            .run();
+
+        // Whether files can be cloned depends on the filesystem.
+        if reflink == Reflink::Always && result.is_err() {
+            continue;
+        }
+        result.unwrap();
+        assert_eq!(fs::read_to_string(&to).unwrap(), "data");
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn sparse() {
+    use std::os::unix::fs::{FileExt, MetadataExt};
+
+    use fuc_engine::Sparse;
+
+    let root = tempdir().unwrap();
+    let holes = root.path().join("holes");
+    {
+        let file = File::create(&holes).unwrap();
+        file.write_all_at(b"data", 1 << 20).unwrap();
+        file.set_len(4 << 20).unwrap();
+    }
+    let zeros = root.path().join("zeros");
+    fs::write(&zeros, vec![0; 1 << 20]).unwrap();
+    let data_blocks = fs::metadata(&holes).unwrap().blocks();
+
+    for (from, sparse) in [
+        (&holes, Sparse::Auto),
+        (&holes, Sparse::Always),
+        (&zeros, Sparse::Always),
+    ] {
+        let to = root.path().join("to");
+        fuc_engine::CopyOp::builder()
+            .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+            .sparse(sparse)
+            .reflink(fuc_engine::Reflink::Never)
+            .build()
+            .run()
+            .unwrap();
+
+        assert_eq!(fs::read(&to).unwrap(), fs::read(from).unwrap());
+        assert!(fs::metadata(&to).unwrap().blocks() <= data_blocks);
+        fs::remove_file(to).unwrap();
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn one_file_system() {
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    let Some(_mount) = common::Mount::tmpfs(&from.join("mnt")) else {
+        return;
+    };
+    fs::write(from.join("a"), "a").unwrap();
+    fs::write(from.join("mnt/b"), "b").unwrap();
+    let to = root.path().join("to");
+
+    fuc_engine::CopyOp::builder()
+        .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+        .one_file_system(true)
+        .build()
+        .run()
+        .unwrap();
+
+    assert!(to.join("a").exists());
+    assert!(to.join("mnt").is_dir());
+    assert!(!to.join("mnt/b").exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn special_files() {
+    use std::os::unix::{fs::FileTypeExt, net::UnixListener};
+
+    use fuc_engine::SpecialFiles;
+    use rustix::fs::{mknodat, FileType, Mode, CWD};
+
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir(&from).unwrap();
+    mknodat(
+        CWD,
+        from.join("fifo"),
+        FileType::Fifo,
+        Mode::from_raw_mode(0o640),
+        0,
+    )
+    .unwrap();
+    let _socket = UnixListener::bind(from.join("socket")).unwrap();
+
+    for (i, special_files) in [
+        SpecialFiles::Recreate,
+        SpecialFiles::Skip,
+        SpecialFiles::Fail,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let to = root.path().join(i.to_string());
+        let result = fuc_engine::CopyOp::builder()
+            .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+            .special_files(special_files)
+            .build()
+            .run();
+
+        match special_files {
+            SpecialFiles::Recreate => {
+                result.unwrap();
+                let metadata = fs::symlink_metadata(to.join("fifo")).unwrap();
+                assert!(metadata.file_type().is_fifo());
+                let metadata = fs::symlink_metadata(to.join("socket")).unwrap();
+                assert!(metadata.file_type().is_socket());
+            }
+            SpecialFiles::Skip => {
+                result.unwrap();
+                assert!(!to.join("fifo").exists());
+                assert!(!to.join("socket").exists());
+            }
+            SpecialFiles::Fail => {
+                assert!(matches!(
+                    result,
+                    Err(fuc_engine::Error::SpecialFile { file })
+                        if file == from.join("fifo") || file == from.join("socket")
+                ));
+            }
+        }
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn special_files_devices() {
+    use std::os::unix::fs::FileTypeExt;
+
+    use fuc_engine::SpecialFiles;
+
+    let root = tempdir().unwrap();
+
+    for (i, special_files) in [SpecialFiles::Recreate, SpecialFiles::Skip]
+        .into_iter()
+        .enumerate()
+    {
+        let to = root.path().join(i.to_string());
+        fs::create_dir(&to).unwrap();
+        let result = fuc_engine::CopyOp::builder()
+            .files([(
+                Cow::Borrowed(Path::new("/dev/zero")),
+                Cow::Owned(to.join("zero")),
+            )])
+            .special_files(special_files)
+            .build()
+            .run();
+
+        // Unprivileged copies must fail instead of reading the device forever.
+        match (special_files, fs::symlink_metadata(to.join("zero"))) {
+            (SpecialFiles::Recreate, Ok(metadata)) => {
+                result.unwrap();
+                assert!(metadata.file_type().is_char_device());
+            }
+            (SpecialFiles::Recreate, Err(_)) => {
+                assert!(matches!(
+                    result,
+                    Err(fuc_engine::Error::Io { error, context: _ })
+                        if error.kind() == std::io::ErrorKind::PermissionDenied
+                ));
+            }
+            (_, metadata) => {
+                result.unwrap();
+                assert!(metadata.is_err());
+            }
+        }
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn follow_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    use fuc_engine::FollowSymlinks;
+
+    let root = tempdir().unwrap();
+    let target = root.path().join("target");
+    fs::create_dir(&target).unwrap();
+    fs::write(target.join("file"), "file").unwrap();
+    let from = root.path().join("from");
+    fs::create_dir(&from).unwrap();
+    symlink(&target, from.join("dir")).unwrap();
+    symlink(target.join("file"), from.join("file")).unwrap();
+    let from_link = root.path().join("from_link");
+    symlink(&from, &from_link).unwrap();
+
+    for (i, follow_symlinks) in [FollowSymlinks::CommandLine, FollowSymlinks::Always]
+        .into_iter()
+        .enumerate()
+    {
+        let to = root.path().join(i.to_string());
+        fuc_engine::CopyOp::builder()
+            .files([(
+                Cow::Borrowed(from_link.as_path()),
+                Cow::Borrowed(to.as_path()),
+            )])
+            .follow_symlinks(follow_symlinks)
+            .build()
+            .run()
+            .unwrap();
+
+        assert!(!to.is_symlink());
+        let inner_symlinks = follow_symlinks == FollowSymlinks::CommandLine;
+        assert_eq!(to.join("dir").is_symlink(), inner_symlinks);
+        assert_eq!(to.join("file").is_symlink(), inner_symlinks);
+        assert_eq!(fs::read_to_string(to.join("dir/file")).unwrap(), "file");
+        assert_eq!(fs::read_to_string(to.join("file")).unwrap(), "file");
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn follow_symlinks_loop() {
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir_all(from.join("nested")).unwrap();
+    std::os::unix::fs::symlink("..", from.join("nested/parent")).unwrap();
+    let to = root.path().join("to");
+
+    let result = fuc_engine::CopyOp::builder()
+        .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+        .follow_symlinks(fuc_engine::FollowSymlinks::Always)
+        .build()
+        .run();
+
+    assert!(matches!(
+        result,
+        Err(fuc_engine::Error::SymlinkLoop { file }) if file == from.join("nested/parent")
+    ));
+}
+
+#[test]
+fn filter() {
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir_all(from.join("target/debug")).unwrap();
+    fs::create_dir_all(from.join("src/target")).unwrap();
+    fs::write(from.join("target/debug/a.o"), "").unwrap();
+    fs::write(from.join("src/main.o"), "").unwrap();
+    fs::write(from.join("src/keep.o"), "").unwrap();
+    fs::write(from.join("src/main.rs"), "").unwrap();
+    fs::write(from.join("target.txt"), "").unwrap();
+    let to = root.path().join("to");
+
+    fuc_engine::CopyOp::builder()
+        .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+        .filter(
+            fuc_engine::Filter::default()
+                .exclude("/target/")
+                .and_then(|filter| filter.exclude("*.o"))
+                .and_then(|filter| filter.include("src/keep.o"))
+                .unwrap(),
+        )
+        .build()
+        .run()
+        .unwrap();
+
+    assert!(!to.join("target").exists());
+    assert!(!to.join("src/main.o").exists());
+    assert!(to.join("src/target").is_dir());
+    assert!(to.join("src/keep.o").exists());
+    assert!(to.join("src/main.rs").exists());
+    assert!(to.join("target.txt").exists());
+    assert!(matches!(
+        fuc_engine::Filter::default().exclude("[a"),
+        Err(fuc_engine::Error::InvalidPattern { pattern }) if pattern == "[a"
+    ));
+}
+
+#[test]
+fn progress() {
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir_all(from.join("nested")).unwrap();
+    fs::write(from.join("a"), "abc").unwrap();
+    fs::write(from.join("nested/b"), "defgh").unwrap();
+    let progress = std::sync::Arc::new(fuc_engine::ProgressCounter::default());
+
+    fuc_engine::CopyOp::builder()
+        .files([(
+            Cow::Borrowed(from.as_path()),
+            Cow::Owned(root.path().join("to")),
+        )])
+        .progress(progress.clone())
+        .build()
+        .run()
+        .unwrap();
+
+    assert_eq!(progress.files(), 2);
+    assert_eq!(progress.dirs(), 2);
+    assert_eq!(progress.bytes(), 8);
+}
+
+#[test]
+fn dry_run() {
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir_all(from.join("nested")).unwrap();
+    fs::write(from.join("nested/a"), "abc").unwrap();
+    let to = root.path().join("to");
+    let planned = std::sync::Arc::new(common::Recorder::default());
+
+    fuc_engine::CopyOp::builder()
+        .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+        .progress(planned.clone())
+        .dry_run(true)
+        .build()
+        .run()
+        .unwrap();
+
+    assert!(!to.exists());
+    let mut planned = planned.planned_paths();
+    planned.sort();
+    assert_eq!(
+        planned,
+        [to.clone(), to.join("nested"), to.join("nested/a")]
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn continue_on_error() {
+    use rustix::fs::{mknodat, FileType, Mode, CWD};
+
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    for dir in ["a", "b"] {
+        fs::create_dir_all(from.join(dir)).unwrap();
+        fs::write(from.join(dir).join("file"), "").unwrap();
+        mknodat(
+            CWD,
+            from.join(dir).join("fifo"),
+            FileType::Fifo,
+            Mode::RUSR | Mode::WUSR,
+            0,
+        )
+        .unwrap();
+    }
+    let to = root.path().join("to");
+
+    let result = fuc_engine::CopyOp::builder()
+        .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+        .special_files(fuc_engine::SpecialFiles::Fail)
+        .continue_on_error(true)
+        .build()
+        .run();
+
+    let Err(fuc_engine::Error::Aggregate { errors }) = result else {
+        panic!("{result:?}");
+    };
+    assert_eq!(errors.len(), 2);
+    assert!(errors
+        .iter()
+        .all(|e| matches!(e, fuc_engine::Error::SpecialFile { .. })));
+    assert!(to.join("a/file").exists());
+    assert!(to.join("b/file").exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn single_threaded() {
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    for i in 0..16 {
+        let nested = from.join(i.to_string());
+        fs::create_dir_all(nested.join("deeper")).unwrap();
+        File::create(nested.join("deeper/file")).unwrap();
+    }
+    let threads = std::sync::Arc::new(common::Recorder::default());
+
+    fuc_engine::CopyOp::builder()
+        .files([(
+            Cow::Borrowed(from.as_path()),
+            Cow::Owned(root.path().join("to")),
+        )])
+        .progress(threads.clone())
+        .threads(std::num::NonZeroUsize::new(1))
+        .build()
+        .run()
+        .unwrap();
+
+    assert!(root.path().join("to/15/deeper/file").exists());
+    assert_eq!(threads.thread_count(), 1);
+}
+
+#[test]
+fn cancel() {
+    #[derive(Debug)]
+    struct CancelAfterOne(fuc_engine::CancellationToken);
+
+    impl fuc_engine::Progress for CancelAfterOne {
+        fn file_done(&self, _: u64) {
+            self.0.cancel();
+        }
+    }
+
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir(&from).unwrap();
+    for i in 0..10 {
+        File::create(from.join(i.to_string())).unwrap();
+    }
+    let cancel = fuc_engine::CancellationToken::default();
+
+    let result = fuc_engine::CopyOp::builder()
+        .files([(
+            Cow::Borrowed(from.as_path()),
+            Cow::Owned(root.path().join("to")),
+        )])
+        .progress(std::sync::Arc::new(CancelAfterOne(cancel.clone())))
+        .cancel(cancel)
+        .build()
+        .run();
+
+    assert!(
+        matches!(result, Err(fuc_engine::Error::Cancelled)),
+        "{result:?}"
+    );
+    assert!(root.path().join("to").exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn update() {
+    use std::time::{Duration, SystemTime};
+
+    use fuc_engine::Update;
+
+    let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let new = old + Duration::from_mins(1);
+    let write = |path: PathBuf, contents: &str, time: SystemTime| {
+        fs::write(&path, contents).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    };
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir(&from).unwrap();
+    let to = root.path().join("to");
+    fs::create_dir(&to).unwrap();
+    write(from.join("newer"), "new", new);
+    write(to.join("newer"), "old", old);
+    write(from.join("older"), "new", old);
+    write(to.join("older"), "old", new);
+    write(from.join("same"), "new", old);
+    write(to.join("same"), "old", old);
+    write(from.join("missing"), "new", old);
+    let copy = |update| {
+        fuc_engine::CopyOp::builder()
+            .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+            .update(update)
+            .build()
+            .run()
+            .unwrap();
+    };
+    let read = |name| fs::read_to_string(to.join(name)).unwrap();
+
+    copy(Update::Older);
+    assert_eq!(read("newer"), "new");
+    assert_eq!(read("older"), "old");
+    assert_eq!(read("same"), "old");
+    assert_eq!(read("missing"), "new");
+
+    copy(Update::Differ);
+    assert_eq!(read("older"), "new");
+    assert_eq!(read("same"), "old");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn update_symlinks() {
+    use std::os::unix::fs::{symlink, MetadataExt};
+
+    use fuc_engine::{Preserve, Update};
+
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir(&from).unwrap();
+    fs::write(from.join("file"), "new").unwrap();
+    symlink("file", from.join("link")).unwrap();
+    let target = root.path().join("target");
+    fs::write(&target, "old").unwrap();
+    let to = root.path().join("to");
+    let copy = |update| {
+        fuc_engine::CopyOp::builder()
+            .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+            .update(update)
+            .preserve(Preserve {
+                timestamps: true,
+                ..Preserve::default()
+            })
+            .build()
+            .run()
+            .unwrap();
+    };
+    let inode = || fs::symlink_metadata(to.join("link")).unwrap().ino();
+
+    copy(Update::Differ);
+    let copied = inode();
+    copy(Update::Differ);
+    assert_eq!(inode(), copied);
+    assert_eq!(fs::read_link(to.join("link")).unwrap(), Path::new("file"));
+
+    // Destination symlinks are never written through.
+    fs::remove_file(to.join("file")).unwrap();
+    symlink(&target, to.join("file")).unwrap();
+    copy(Update::Never);
+    assert!(to.join("file").is_symlink());
+    copy(Update::Differ);
+    assert!(!to.join("file").is_symlink());
+    assert_eq!(fs::read_to_string(to.join("file")).unwrap(), "new");
+    assert_eq!(fs::read_to_string(&target).unwrap(), "old");
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn atomic_files() {
+    use fuc_engine::{Atomic, Preserve};
+
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir_all(from.join("nested")).unwrap();
+    fs::write(from.join("a"), "new").unwrap();
+    fs::hard_link(from.join("a"), from.join("nested/b")).unwrap();
+    let to = root.path().join("to");
+    fs::create_dir_all(to.join("nested")).unwrap();
+    fs::write(to.join("a"), "old").unwrap();
+    // Files written in place would show up through this link too.
+    fs::hard_link(to.join("a"), root.path().join("old")).unwrap();
+
+    fuc_engine::CopyOp::builder()
+        .files([(Cow::Owned(from), Cow::Borrowed(to.as_path()))])
+        .force(true)
+        .preserve(Preserve {
+            mode: true,
+            ..Preserve::default()
+        })
+        .atomic(Atomic::Files)
+        .build()
+        .run()
+        .unwrap();
+
+    assert_eq!(fs::read_to_string(to.join("a")).unwrap(), "new");
+    assert_eq!(fs::read_to_string(to.join("nested/b")).unwrap(), "new");
+    assert_eq!(fs::read_to_string(root.path().join("old")).unwrap(), "old");
+    assert_eq!(fs::read_dir(&to).unwrap().count(), 2);
+    assert_eq!(fs::read_dir(to.join("nested")).unwrap().count(), 1);
+}
+
+#[test]
+fn atomic_trees() {
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir(&from).unwrap();
+    fs::write(from.join("a"), "new").unwrap();
+    let to = root.path().join("to");
+    fs::create_dir(&to).unwrap();
+    fs::write(to.join("a"), "old").unwrap();
+    File::create(to.join("b")).unwrap();
+
+    fuc_engine::CopyOp::builder()
+        .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+        .force(true)
+        .atomic(fuc_engine::Atomic::Trees)
+        .build()
+        .run()
+        .unwrap();
+
+    assert_eq!(fs::read_to_string(to.join("a")).unwrap(), "new");
+    assert!(!to.join("b").exists());
+    assert_eq!(fs::read_dir(root.path()).unwrap().count(), 2);
+}
+
+#[test]
+fn mirror() {
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir_all(from.join("nested")).unwrap();
+    File::create(from.join("a")).unwrap();
+    File::create(from.join("nested/b")).unwrap();
+    let to = root.path().join("to");
+    fs::create_dir_all(to.join("nested/extra_dir/deeper")).unwrap();
+    File::create(to.join("extra")).unwrap();
+    File::create(to.join("excluded")).unwrap();
+    File::create(to.join("nested/extra")).unwrap();
+    File::create(to.join("nested/extra_dir/deeper/c")).unwrap();
+    let mirror = |dry_run, progress| {
+        fuc_engine::CopyOp::builder()
+            .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+            .force(true)
+            .mirror(true)
+            .filter(fuc_engine::Filter::default().exclude("excluded").unwrap())
+            .progress(progress)
+            .dry_run(dry_run)
+            .build()
+            .run()
+            .unwrap();
+    };
+
+    let planned = std::sync::Arc::new(common::Recorder::default());
+    mirror(true, planned.clone());
+
+    assert!(to.join("extra").exists());
+    let planned = planned.planned_paths();
+    for removed in ["extra", "nested/extra", "nested/extra_dir/deeper/c"] {
+        assert!(planned.contains(&to.join(removed)), "{planned:?}");
+    }
+    assert!(!planned.contains(&to.join("excluded")), "{planned:?}");
+
+    mirror(false, std::sync::Arc::new(()));
+
+    assert!(to.join("a").exists());
+    assert!(to.join("nested/b").exists());
+    assert!(to.join("excluded").exists());
+    assert!(!to.join("extra").exists());
+    assert!(!to.join("nested/extra").exists());
+    assert!(!to.join("nested/extra_dir").exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn backup() {
+    use fuc_engine::{Atomic, Backup};
+
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::write(&from, "new").unwrap();
+    let to = root.path().join("to");
+    let copy = |backup, atomic| {
+        fuc_engine::CopyOp::builder()
+            .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+            .force(true)
+            .backup(backup)
+            .backup_suffix(".bak".to_owned())
+            .atomic(atomic)
+            .build()
+            .run()
+            .unwrap();
+    };
+    let read = |suffix| fs::read_to_string(root.path().join(format!("to{suffix}"))).unwrap();
+
+    fs::write(&to, "old").unwrap();
+    copy(Backup::Simple, Atomic::Never);
+    assert_eq!(read(""), "new");
+    assert_eq!(read(".bak"), "old");
+
+    fs::write(&to, "old 1").unwrap();
+    copy(Backup::Numbered, Atomic::Never);
+    fs::write(&to, "old 2").unwrap();
+    copy(Backup::Numbered, Atomic::Files);
+    assert_eq!(read(""), "new");
+    assert_eq!(read(".~1~"), "old 1");
+    assert_eq!(read(".~2~"), "old 2");
+
+    // New backups go after the highest one, even once earlier ones are gone.
+    fs::remove_file(root.path().join("to.~1~")).unwrap();
+    fs::write(root.path().join("top.~5~"), "").unwrap();
+    fs::write(&to, "old 3").unwrap();
+    copy(Backup::Numbered, Atomic::Never);
+    assert_eq!(read(".~3~"), "old 3");
+    assert!(!root.path().join("to.~1~").exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn mirror_backup() {
+    use fuc_engine::Backup;
+
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::create_dir(&from).unwrap();
+    fs::write(from.join("a"), "new").unwrap();
+    let to = root.path().join("to");
+    fs::create_dir(&to).unwrap();
+    let copy = |backup| {
+        fs::write(to.join("a"), "old").unwrap();
+        fs::write(to.join("gone"), "").unwrap();
+        fs::write(to.join("gone~"), "").unwrap();
+        fs::write(to.join("gone.~1~"), "").unwrap();
+        fuc_engine::CopyOp::builder()
+            .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+            .force(true)
+            .mirror(true)
+            .backup(backup)
+            .build()
+            .run()
+            .unwrap();
+    };
+
+    copy(Backup::Simple);
+    assert_eq!(fs::read_to_string(to.join("a~")).unwrap(), "old");
+    assert!(!to.join("gone").exists());
+    assert!(!to.join("gone~").exists());
+
+    copy(Backup::Numbered);
+    assert_eq!(fs::read_to_string(to.join("a.~1~")).unwrap(), "old");
+    assert!(!to.join("gone.~1~").exists());
+    // Only backups in the current style are kept.
+    assert!(!to.join("a~").exists());
+}
@@ -0,0 +1,171 @@
+use std::{borrow::Cow, fs, fs::File};
+
+use tempfile::tempdir;
+
+#[test]
+fn one_file() {
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::write(&from, "abc").unwrap();
+    let to = root.path().join("to");
+
+    fuc_engine::move_file(&from, &to).unwrap();
+
+    assert!(!from.exists());
+    assert_eq!(fs::read_to_string(to).unwrap(), "abc");
+}
+
+#[test]
+fn pre_existing_file_no_force() {
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    File::create(&from).unwrap();
+    let to = root.path().join("to");
+    File::create(&to).unwrap();
+
+    let result = fuc_engine::MoveOp::builder()
+        .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+        .force(false)
+        .build()
+        .run();
+
+    assert!(
+        matches!(result, Err(fuc_engine::Error::AlreadyExists { ref file }) if *file == to),
+        "{result:?}"
+    );
+    assert!(from.exists());
+}
+
+#[test]
+fn pre_existing_file_force() {
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+    fs::write(&from, "abc").unwrap();
+    let to = root.path().join("to");
+    File::create(&to).unwrap();
+
+    fuc_engine::MoveOp::builder()
+        .files([(Cow::Borrowed(from.as_path()), Cow::Borrowed(to.as_path()))])
+        .force(true)
+        .build()
+        .run()
+        .unwrap();
+
+    assert!(!from.exists());
+    assert_eq!(fs::read_to_string(to).unwrap(), "abc");
+}
+
+#[test]
+fn non_existent_file() {
+    let root = tempdir().unwrap();
+    let from = root.path().join("from");
+
+    let result = fuc_engine::move_file(&from, root.path().join("to"));
+
+    assert!(
+        matches!(result, Err(fuc_engine::Error::NotFound { ref file }) if *file == from),
+        "{result:?}"
+    );
+}
+
+/// A directory on a different filesystem than `root`, if one is available.
+#[cfg(target_os = "linux")]
+fn other_device(root: &std::path::Path) -> Option<tempfile::TempDir> {
+    use std::{os::unix::fs::MetadataExt, path::Path};
+
+    let shm = Path::new("/dev/shm");
+    if shm.is_dir() && shm.metadata().unwrap().dev() != root.metadata().unwrap().dev() {
+        Some(tempfile::tempdir_in(shm).unwrap())
+    } else {
+        eprintln!("Skipping test: /dev/shm isn't on a different filesystem");
+        None
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn cross_device() {
+    use std::path::Path;
+
+    let root = tempdir().unwrap();
+    let Some(other) = other_device(root.path()) else {
+        return;
+    };
+    let from = root.path().join("from");
+    fs::create_dir_all(from.join("nested")).unwrap();
+    fs::write(from.join("nested/a"), "abc").unwrap();
+    std::os::unix::fs::symlink("nested/a", from.join("link")).unwrap();
+    let to = other.path().join("to");
+
+    fuc_engine::move_file(&from, &to).unwrap();
+
+    assert!(!from.exists());
+    assert_eq!(fs::read_to_string(to.join("nested/a")).unwrap(), "abc");
+    assert_eq!(
+        fs::read_link(to.join("link")).unwrap(),
+        Path::new("nested/a")
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn cross_device_force() {
+    use std::{io, os::unix::fs::symlink, path::Path};
+
+    let root = tempdir().unwrap();
+    let Some(other) = other_device(root.path()) else {
+        return;
+    };
+    let from = root.path().join("from");
+    fs::create_dir_all(from.join("dir")).unwrap();
+    fs::write(from.join("dir/a"), "abc").unwrap();
+    fs::write(from.join("file"), "abc").unwrap();
+    symlink("file", from.join("link")).unwrap();
+    let to = other.path().join("to");
+    fs::create_dir_all(to.join("dir")).unwrap();
+    fs::write(to.join("file"), "def").unwrap();
+    fs::write(to.join("link"), "def").unwrap();
+    fs::create_dir_all(to.join("full/b")).unwrap();
+    fs::create_dir(to.join("not_file")).unwrap();
+
+    let run = |files: &[(&str, &str)]| {
+        fuc_engine::MoveOp::builder()
+            .files(
+                files
+                    .iter()
+                    .map(|&(from_name, to_name)| (from.join(from_name), to.join(to_name))),
+            )
+            .force(true)
+            .build()
+            .run()
+    };
+
+    // Nothing is touched when a rename would have been refused.
+    for (from_name, to_name, kind) in [
+        ("dir", "full", io::ErrorKind::DirectoryNotEmpty),
+        ("dir", "file", io::ErrorKind::NotADirectory),
+        ("file", "not_file", io::ErrorKind::IsADirectory),
+    ] {
+        let result = run(&[("link", "link"), (from_name, to_name)]);
+        assert!(
+            matches!(
+                &result,
+                Err(fuc_engine::Error::Io { error, context: _ }) if error.kind() == kind
+            ),
+            "{result:?}"
+        );
+        assert!(from.join(from_name).exists());
+        assert!(to.join("full/b").exists());
+        assert_eq!(fs::read_to_string(to.join("file")).unwrap(), "def");
+        assert_eq!(fs::read_to_string(to.join("link")).unwrap(), "def");
+    }
+
+    run(&[("dir", "dir"), ("file", "file"), ("link", "link")]).unwrap();
+
+    assert!(!from.join("dir").exists());
+    assert!(!from.join("file").exists());
+    assert!(!from.join("link").exists());
+    assert_eq!(fs::read_to_string(to.join("dir/a")).unwrap(), "abc");
+    assert_eq!(fs::read_to_string(to.join("file")).unwrap(), "abc");
+    assert_eq!(fs::read_link(to.join("link")).unwrap(), Path::new("file"));
+}
@@ -1,10 +1,12 @@
-use std::{borrow::Cow, fs, fs::File, io, num::NonZeroU64};
+use std::{borrow::Cow, fs, fs::File, io, num::NonZeroU64, path::Path};
 
 use ftzz::generator::{Generator, NumFilesWithRatio};
 use io_adapters::WriteExtension;
 use rstest::rstest;
 use tempfile::tempdir;
 
+mod common;
+
 #[test]
 fn non_existent_file_no_force() {
     let root = tempdir().unwrap();
@@ -37,6 +39,143 @@ fn non_existent_file_force() {
     assert!(root.path().exists());
 }
 
+#[test]
+fn continue_on_error() {
+    let root = tempdir().unwrap();
+    let file = root.path().join("file");
+    File::create(&file).unwrap();
+    let missing = [root.path().join("a"), root.path().join("b")];
+
+    let result = fuc_engine::RemoveOp::builder()
+        .files([
+            Cow::Borrowed(missing[0].as_path()),
+            Cow::Borrowed(file.as_path()),
+            Cow::Borrowed(missing[1].as_path()),
+        ])
+        .continue_on_error(true)
+        .build()
+        .run();
+
+    let Err(fuc_engine::Error::Aggregate { errors }) = result else {
+        panic!("{result:?}");
+    };
+    assert!(matches!(
+        &errors[..],
+        [
+            fuc_engine::Error::NotFound { file: a },
+            fuc_engine::Error::NotFound { file: b },
+        ] if *a == missing[0] && *b == missing[1]
+    ));
+    assert!(!file.exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn continue_on_error_keeps_parents() {
+    let root = tempdir().unwrap();
+    let dir = root.path().join("dir");
+    let Some(mount) = common::Mount::tmpfs(&dir.join("a")) else {
+        return;
+    };
+    fs::create_dir(dir.join("a/b")).unwrap();
+    File::create(dir.join("a/b/file")).unwrap();
+    File::create(dir.join("file")).unwrap();
+    mount.remount_read_only();
+
+    let result = fuc_engine::RemoveOp::builder()
+        .files([Cow::Borrowed(dir.as_path())])
+        .continue_on_error(true)
+        .build()
+        .run();
+
+    // Only the file is reported, not every directory that couldn't be emptied.
+    let Err(fuc_engine::Error::Aggregate { errors }) = result else {
+        panic!("{result:?}");
+    };
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    assert!(dir.join("a/b/file").exists());
+    assert!(!dir.join("file").exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn one_file_system() {
+    let root = tempdir().unwrap();
+    let dir = root.path().join("dir");
+    let Some(_mount) = common::Mount::tmpfs(&dir.join("mnt")) else {
+        return;
+    };
+    File::create(dir.join("a")).unwrap();
+    File::create(dir.join("mnt/b")).unwrap();
+
+    let result = fuc_engine::RemoveOp::builder()
+        .files([Cow::Borrowed(dir.as_path())])
+        .one_file_system(true)
+        .build()
+        .run();
+
+    assert!(result.is_err(), "{result:?}");
+    assert!(!dir.join("a").exists());
+    assert!(dir.join("mnt/b").exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn single_threaded() {
+    let root = tempdir().unwrap();
+    let dir = root.path().join("dir");
+    for i in 0..16 {
+        let nested = dir.join(i.to_string());
+        fs::create_dir_all(nested.join("deeper")).unwrap();
+        File::create(nested.join("deeper/file")).unwrap();
+    }
+    let threads = std::sync::Arc::new(common::Recorder::default());
+
+    fuc_engine::RemoveOp::builder()
+        .files([Cow::Borrowed(dir.as_path())])
+        .progress(threads.clone())
+        .threads(std::num::NonZeroUsize::new(1))
+        .build()
+        .run()
+        .unwrap();
+
+    assert!(!dir.exists());
+    assert_eq!(threads.thread_count(), 1);
+}
+
+#[test]
+fn cancel() {
+    #[derive(Debug)]
+    struct CancelAfterOne(fuc_engine::CancellationToken);
+
+    impl fuc_engine::Progress for CancelAfterOne {
+        fn file_done(&self, _: u64) {
+            self.0.cancel();
+        }
+    }
+
+    let root = tempdir().unwrap();
+    let dir = root.path().join("dir");
+    fs::create_dir(&dir).unwrap();
+    for i in 0..10 {
+        File::create(dir.join(i.to_string())).unwrap();
+    }
+    let cancel = fuc_engine::CancellationToken::default();
+
+    let result = fuc_engine::RemoveOp::builder()
+        .files([Cow::Borrowed(dir.as_path())])
+        .progress(std::sync::Arc::new(CancelAfterOne(cancel.clone())))
+        .cancel(cancel)
+        .build()
+        .run();
+
+    assert!(
+        matches!(result, Err(fuc_engine::Error::Cancelled)),
+        "{result:?}"
+    );
+    assert!(dir.exists());
+}
+
 #[test]
 fn one_file() {
     let root = tempdir().unwrap();
@@ -63,6 +202,50 @@ fn one_dir() {
     assert!(root.path().exists());
 }
 
+#[test]
+fn progress() {
+    let root = tempdir().unwrap();
+    let dir = root.path().join("dir");
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    File::create(dir.join("a")).unwrap();
+    File::create(dir.join("nested/b")).unwrap();
+    let progress = std::sync::Arc::new(fuc_engine::ProgressCounter::default());
+
+    fuc_engine::RemoveOp::builder()
+        .files([Cow::Borrowed(dir.as_path())])
+        .progress(progress.clone())
+        .build()
+        .run()
+        .unwrap();
+
+    assert!(!dir.exists());
+    assert_eq!(progress.files(), 2);
+    assert_eq!(progress.dirs(), 2);
+}
+
+#[test]
+fn dry_run() {
+    let root = tempdir().unwrap();
+    let dir = root.path().join("dir");
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    File::create(dir.join("nested/a")).unwrap();
+    let planned = std::sync::Arc::new(common::Recorder::default());
+
+    fuc_engine::RemoveOp::builder()
+        .files([Cow::Borrowed(dir.as_path())])
+        .progress(planned.clone())
+        .dry_run(true)
+        .build()
+        .run()
+        .unwrap();
+
+    assert!(dir.join("nested/a").exists());
+    assert_eq!(
+        planned.planned_paths(),
+        [dir.join("nested/a"), dir.join("nested"), dir.clone()]
+    );
+}
+
 #[test]
 #[cfg(unix)]
 fn symbolic_link_delete_dir() {
@@ -139,3 +322,297 @@ fn uniform(#[values(1_000, 100_000)] num_files: u64) {
     assert!(!dir.exists());
     assert!(root.path().exists());
 }
+
+#[test]
+#[cfg(target_os = "linux")]
+fn trash() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let root = tempdir().unwrap();
+    let dir = root.path().join("dir");
+    fs::create_dir(&dir).unwrap();
+    File::create(dir.join("file")).unwrap();
+    let trash = root.path().join("Trash");
+    std::env::set_var("XDG_DATA_HOME", root.path());
+
+    for _ in 0..2 {
+        fuc_engine::RemoveOp::builder()
+            .files([dir.as_path()])
+            .trash(fuc_engine::Trash::Always)
+            .build()
+            .run()
+            .unwrap();
+        assert!(!dir.exists());
+        fs::create_dir(&dir).unwrap();
+    }
+
+    assert!(trash.join("files/dir/file").exists());
+    assert!(trash.join("files/dir.2").exists());
+    let info = fs::read_to_string(trash.join("info/dir.trashinfo")).unwrap();
+    assert!(
+        info.starts_with(&format!(
+            "[Trash Info]\nPath={}\nDeletionDate=",
+            dir.canonicalize().unwrap().display()
+        )),
+        "{info}"
+    );
+    assert!(trash.join("info/dir.2.trashinfo").exists());
+
+    File::create(dir.join("file")).unwrap();
+    fuc_engine::RemoveOp::builder()
+        .files([dir.as_path()])
+        .contents_only(true)
+        .trash(fuc_engine::Trash::Always)
+        .build()
+        .run()
+        .unwrap();
+    assert!(dir.exists());
+    assert!(trash.join("files/file").exists());
+
+    // Files on other mounts go to the trash at the top of their mount, even when
+    // they share a device with the home trash.
+    let trash_file = |file: &Path| {
+        File::create(file).unwrap();
+        fuc_engine::RemoveOp::builder()
+            .files([file])
+            .trash(fuc_engine::Trash::Always)
+            .build()
+            .run()
+    };
+    let uid = fs::metadata(root.path()).unwrap().uid();
+    let Some(_bind) = common::Mount::bind(&dir, &root.path().join("bind")) else {
+        return;
+    };
+    trash_file(&root.path().join("bind/a")).unwrap();
+    assert!(root
+        .path()
+        .join(format!("bind/.Trash-{uid}/files/a"))
+        .exists());
+
+    let Some(_tmpfs) = common::Mount::tmpfs(&root.path().join("tmpfs")) else {
+        return;
+    };
+    let top_dir_trash = root.path().join(format!("tmpfs/.Trash-{uid}"));
+    trash_file(&root.path().join("tmpfs/b")).unwrap();
+    assert!(top_dir_trash.join("files/b").exists());
+
+    // Trashes others could get into aren't used.
+    fs::set_permissions(&top_dir_trash, fs::Permissions::from_mode(0o755)).unwrap();
+    trash_file(&root.path().join("tmpfs/c")).unwrap_err();
+    assert!(root.path().join("tmpfs/c").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn confirm() {
+    #[derive(Debug)]
+    struct KeepKept;
+
+    impl fuc_engine::Confirm for KeepKept {
+        fn confirm(&self, path: &Path, _: bool) -> bool {
+            !path.ends_with("kept")
+        }
+
+        fn confirms_entries(&self) -> bool {
+            true
+        }
+
+        fn confirm_entry(&self, path: &Path, _: bool) -> bool {
+            !path.ends_with("kept")
+        }
+
+        fn confirm_emptied(&self, path: &Path) -> bool {
+            !path.ends_with("emptied")
+        }
+    }
+
+    let root = tempdir().unwrap();
+    let kept = root.path().join("kept");
+    let dir = root.path().join("dir");
+    fs::create_dir_all(dir.join("a/b")).unwrap();
+    fs::create_dir_all(dir.join("c")).unwrap();
+    File::create(&kept).unwrap();
+    File::create(dir.join("a/b/kept")).unwrap();
+    File::create(dir.join("a/file")).unwrap();
+    File::create(dir.join("c/file")).unwrap();
+    fs::create_dir_all(dir.join("emptied")).unwrap();
+    File::create(dir.join("emptied/file")).unwrap();
+
+    fuc_engine::RemoveOp::builder()
+        .files([kept.as_path(), dir.as_path()])
+        .confirm(std::sync::Arc::new(KeepKept))
+        .build()
+        .run()
+        .unwrap();
+
+    assert!(kept.exists());
+    assert!(dir.join("a/b/kept").exists());
+    assert!(!dir.join("a/file").exists());
+    assert!(!dir.join("c").exists());
+    assert!(dir.join("emptied").exists());
+    assert!(!dir.join("emptied/file").exists());
+}
+
+#[test]
+fn contents_only() {
+    let root = tempdir().unwrap();
+    let dir = root.path().join("dir");
+    let file = root.path().join("file");
+    fs::create_dir_all(dir.join("a/b")).unwrap();
+    File::create(dir.join("a/b/file")).unwrap();
+    File::create(dir.join("file")).unwrap();
+    File::create(&file).unwrap();
+
+    fuc_engine::RemoveOp::builder()
+        .files([dir.as_path()])
+        .contents_only(true)
+        .build()
+        .run()
+        .unwrap();
+
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+    fuc_engine::RemoveOp::builder()
+        .files([file.as_path()])
+        .contents_only(true)
+        .build()
+        .run()
+        .unwrap_err();
+
+    assert!(file.exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn fix_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = tempdir().unwrap();
+    let parent = root.path().join("parent");
+    let dir = parent.join("dir");
+    let file = parent.join("file");
+    fs::create_dir_all(dir.join("a/b")).unwrap();
+    fs::create_dir_all(dir.join("c")).unwrap();
+    File::create(dir.join("a/file")).unwrap();
+    File::create(dir.join("a/b/file")).unwrap();
+    File::create(dir.join("c/file")).unwrap();
+    File::create(&file).unwrap();
+    for (path, mode) in [
+        ("dir/a/b", 0o555),
+        ("dir/a", 0o500),
+        ("dir/c", 0o000),
+        ("dir", 0o555),
+        ("", 0o555),
+    ] {
+        fs::set_permissions(parent.join(path), fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    fuc_engine::RemoveOp::builder()
+        .files([dir.as_path(), file.as_path()])
+        .fix_permissions(true)
+        .build()
+        .run()
+        .unwrap();
+
+    assert!(!dir.exists());
+    assert!(!file.exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn background() {
+    use std::{path::PathBuf, sync::Mutex};
+
+    #[derive(Debug, Default)]
+    struct Staged(Mutex<Vec<PathBuf>>);
+
+    impl fuc_engine::Purge for Staged {
+        fn purge(&self, staging: Vec<PathBuf>) -> Result<(), fuc_engine::Error> {
+            self.0.lock().unwrap().extend(staging);
+            Ok(())
+        }
+    }
+
+    let root = tempdir().unwrap();
+    let dir = root.path().join("dir");
+    let staging = root.path().join(".fuc-staging");
+    fs::create_dir_all(dir.join("a")).unwrap();
+    File::create(dir.join("a/file")).unwrap();
+    fs::create_dir_all(staging.join("leftover")).unwrap();
+
+    let purge = std::sync::Arc::new(Staged::default());
+    fuc_engine::RemoveOp::builder()
+        .files([dir.as_path()])
+        .background(Some(purge.clone()))
+        .build()
+        .run()
+        .unwrap();
+
+    assert!(!dir.exists());
+    assert_eq!(*purge.0.lock().unwrap(), [staging.as_path()]);
+    assert_eq!(fs::read_dir(&staging).unwrap().count(), 2);
+
+    // Leftovers are purged even when nothing new is staged next to them.
+    let empty = root.path().join("empty");
+    fs::create_dir_all(empty.join(".fuc-staging/leftover")).unwrap();
+    let purge = std::sync::Arc::new(Staged::default());
+    fuc_engine::RemoveOp::builder()
+        .files([empty.as_path()])
+        .contents_only(true)
+        .background(Some(purge.clone()))
+        .build()
+        .run()
+        .unwrap();
+
+    assert_eq!(*purge.0.lock().unwrap(), [empty.join(".fuc-staging")]);
+}
+
+#[test]
+#[cfg(unix)]
+fn predicate() {
+    use std::time::{Duration, SystemTime};
+
+    let root = tempdir().unwrap();
+    let dir = root.path().join("dir");
+    fs::create_dir_all(dir.join("logs")).unwrap();
+    fs::create_dir_all(dir.join("src")).unwrap();
+    File::create(dir.join("logs/new.log")).unwrap();
+    File::create(dir.join("src/main.rs")).unwrap();
+    let old = SystemTime::now() - Duration::from_hours(1);
+    for file in ["old.log", "logs/old.log"] {
+        File::create(dir.join(file))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+    }
+
+    fuc_engine::RemoveOp::builder()
+        .files([dir.as_path()])
+        .predicate(
+            fuc_engine::Predicate::default()
+                .name("*.log")
+                .unwrap()
+                .older_than(
+                    fuc_engine::Timestamp::Modified,
+                    SystemTime::now() - Duration::from_mins(1),
+                ),
+        )
+        .build()
+        .run()
+        .unwrap();
+
+    assert!(!dir.join("old.log").exists());
+    assert!(!dir.join("logs/old.log").exists());
+    assert!(dir.join("logs/new.log").exists());
+    assert!(dir.join("src/main.rs").exists());
+
+    fuc_engine::RemoveOp::builder()
+        .files([dir.as_path()])
+        .predicate(fuc_engine::Predicate::default().name("*.rs").unwrap())
+        .build()
+        .run()
+        .unwrap();
+
+    assert_eq!(fs::read_dir(dir.join("src")).unwrap().count(), 0);
+    assert!(dir.join("logs/new.log").exists());
+}
@@ -0,0 +1,259 @@
+#![feature(lazy_cell)]
+
+use std::{
+    cell::LazyCell,
+    fs,
+    mem::swap,
+    num::NonZeroUsize,
+    path::{PathBuf, MAIN_SEPARATOR, MAIN_SEPARATOR_STR},
+    sync::OnceLock,
+};
+
+use clap::{ArgAction, Parser, ValueHint};
+use error_stack::Report;
+use fuc_engine::{CancellationToken, Error, MoveOp};
+
+/// A zippy alternative to `mv`, a tool to move files and directories
+#[derive(Parser, Debug)]
+#[command(version, author = "Alex Saveau (@SUPERCILEX)")]
+#[command(infer_subcommands = true, infer_long_args = true)]
+#[command(disable_help_flag = true)]
+#[command(arg_required_else_help = true)]
+#[command(max_term_width = 100)]
+#[cfg_attr(test, command(help_expected = true))]
+struct Mvz {
+    /// The file(s) or directory(ies) to be moved
+    ///
+    /// If multiple files are specified, they will be moved into the target
+    /// destination rather than to it. The same is true of directory names
+    /// (`foo/`, `.`, `..`): that is, `mvz a b/` places `a` inside `b` as
+    /// opposed to `mvz a b` which makes `b` become `a`.
+    #[arg(required = true)]
+    #[arg(value_hint = ValueHint::AnyPath)]
+    from: Vec<PathBuf>,
+
+    /// The move destination
+    #[arg(required = true)]
+    #[arg(value_hint = ValueHint::AnyPath)]
+    to: PathBuf,
+
+    /// Overwrite existing files
+    #[arg(short, long, default_value_t = false)]
+    force: bool,
+
+    /// The maximum number of threads to move with when files have to be
+    /// copied across filesystems
+    ///
+    /// Defaults to the number of available CPUs. Use `1` to copy files one at a time.
+    #[arg(short = 'j', long, value_name = "N", env = "FUC_THREADS")]
+    threads: Option<NonZeroUsize>,
+
+    /// Reverse the argument order so that it becomes `mvz <TO> <FROM>...`
+    #[arg(short = 't', long, default_value_t = false)]
+    reverse_args: bool,
+
+    #[arg(short, long, short_alias = '?', global = true)]
+    #[arg(action = ArgAction::Help, help = "Print help (use `--help` for more detail)")]
+    #[arg(long_help = "Print help (use `-h` for a summary)")]
+    help: Option<bool>,
+}
+
+#[derive(thiserror::Error, Debug)]
+enum CliError {
+    #[error("{0}")]
+    Wrapper(String),
+}
+
+#[cfg(feature = "trace")]
+#[global_allocator]
+static GLOBAL: tracy_client::ProfiledAllocator<std::alloc::System> =
+    tracy_client::ProfiledAllocator::new(std::alloc::System, 100);
+
+fn main() -> error_stack::Result<(), CliError> {
+    #[cfg(not(debug_assertions))]
+    error_stack::Report::install_debug_hook::<std::panic::Location>(|_, _| {});
+
+    #[cfg(feature = "trace")]
+    {
+        use tracing_subscriber::{
+            fmt::format::DefaultFields, layer::SubscriberExt, util::SubscriberInitExt,
+        };
+
+        #[derive(Default)]
+        struct Config(DefaultFields);
+
+        impl tracing_tracy::Config for Config {
+            type Formatter = DefaultFields;
+
+            fn formatter(&self) -> &Self::Formatter {
+                &self.0
+            }
+
+            fn stack_depth(&self, _: &tracing::Metadata<'_>) -> u16 {
+                32
+            }
+
+            fn format_fields_in_zone_name(&self) -> bool {
+                false
+            }
+        }
+
+        tracing_subscriber::registry()
+            .with(tracing_tracy::TracyLayer::new(Config::default()))
+            .init();
+    };
+
+    let args = Mvz::parse();
+    let cancel = cancel_on_interrupt();
+
+    mv(args, cancel).map_err(into_report)
+}
+
+fn into_report(e: Error) -> Report<CliError> {
+    let wrapper = CliError::Wrapper(format!("{e}"));
+    match e {
+        Error::Io { error, context } => Report::from(error)
+            .attach_printable(context)
+            .change_context(wrapper),
+        Error::AlreadyExists { file } => {
+            let report = Report::from(wrapper);
+            match file.symlink_metadata().map(|m| m.is_dir()) {
+                Ok(true) => {
+                    let mut file = file.into_os_string();
+                    file.push(MAIN_SEPARATOR_STR);
+                    report.attach_printable(format!(
+                        "Use the path {file:?} to move into the directory."
+                    ))
+                }
+                Ok(false) | Err(_) => report.attach_printable("Use --force to overwrite."),
+            }
+        }
+        Error::NotFound { file: _ }
+        | Error::SpecialFile { file: _ }
+        | Error::SymlinkLoop { file: _ }
+        | Error::PreserveRoot
+        | Error::Join
+        | Error::BadPath
+        | Error::Cancelled
+        | Error::Internal => Report::from(wrapper),
+        Error::InvalidPattern { pattern: _ } | Error::Aggregate { errors: _ } => unreachable!(),
+    }
+}
+
+fn mv(
+    Mvz {
+        mut from,
+        mut to,
+        force,
+        threads,
+        reverse_args,
+        help: _,
+    }: Mvz,
+    cancel: CancellationToken,
+) -> Result<(), Error> {
+    if reverse_args {
+        swap(&mut to, &mut from[0]);
+    }
+    let from = from;
+    let to = to;
+
+    #[allow(clippy::unnested_or_patterns)]
+    let is_into_directory = LazyCell::new(|| {
+        matches!(
+            {
+                let path_str = to.to_string_lossy();
+                let mut chars = path_str.chars();
+                (chars.next_back(), chars.next_back(), chars.next_back())
+            },
+            (Some(MAIN_SEPARATOR), _, _) // */
+                | (Some('.'), None, _) // .
+                | (Some('.'), Some(MAIN_SEPARATOR), _) // */.
+                | (Some('.'), Some('.'), None) // ..
+                | (Some('.'), Some('.'), Some(MAIN_SEPARATOR)) // */..
+        )
+    });
+    if from.len() > 1 || *is_into_directory {
+        fs::create_dir_all(&to).map_err(|error| Error::Io {
+            error,
+            context: format!("Failed to create directory {to:?}").into(),
+        })?;
+    }
+
+    if from.len() > 1 {
+        MoveOp::builder()
+            .files(from.into_iter().map(|path| {
+                let to = path
+                    .file_name()
+                    .map_or_else(|| to.clone(), |name| to.join(name));
+                (path, to)
+            }))
+            .force(force)
+            .threads(threads)
+            .cancel(cancel)
+            .build()
+            .run()
+    } else {
+        MoveOp::builder()
+            .files([{
+                let from = from.into_iter().next().unwrap();
+                let to = {
+                    let is_into_directory = *is_into_directory;
+                    let mut to = to;
+                    if is_into_directory {
+                        if let Some(name) = from.file_name() {
+                            to.push(name);
+                        }
+                    }
+                    to
+                };
+
+                (from, to)
+            }])
+            .force(force)
+            .threads(threads)
+            .cancel(cancel)
+            .build()
+            .run()
+    }
+}
+
+/// Turns the first Ctrl-C into a clean stop between directory entries. The
+/// handler resets itself, so a second Ctrl-C kills the process as usual.
+fn cancel_on_interrupt() -> CancellationToken {
+    static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+    extern "C" fn handle(_: libc::c_int) {
+        if let Some(token) = TOKEN.get() {
+            token.cancel();
+        }
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+
+    let token = TOKEN.get_or_init(CancellationToken::default).clone();
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    token
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn verify_app() {
+        Mvz::command().debug_assert();
+    }
+
+    #[test]
+    fn help_for_review() {
+        supercilex_tests::help_for_review(Mvz::command());
+    }
+}
@@ -0,0 +1,4 @@
+#[test]
+fn readme() {
+    trycmd::TestCases::new().case("README.md");
+}
@@ -1,8 +1,22 @@
-use std::path::PathBuf;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::{
+    env,
+    io::{self, BufRead, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{mpsc, Arc, Mutex, OnceLock, PoisonError},
+    thread,
+    time::{Duration, SystemTime},
+};
 
-use clap::{ArgAction, Parser, ValueHint};
+use clap::{ArgAction, Parser, ValueEnum, ValueHint};
 use error_stack::Report;
-use fuc_engine::{Error, RemoveOp};
+use fuc_engine::{
+    CancellationToken, Confirm, Error, FileKind, Predicate, Progress, ProgressCounter, Purge,
+    RemoveOp, Timestamp, Trash,
+};
 
 /// A zippy alternative to `rm`, a tool to remove files and directories
 #[derive(Parser, Debug)]
@@ -12,27 +26,172 @@ use fuc_engine::{Error, RemoveOp};
 #[command(arg_required_else_help = true)]
 #[command(max_term_width = 100)]
 #[cfg_attr(test, command(help_expected = true))]
+#[allow(clippy::struct_excessive_bools)]
 struct Rmz {
     /// The files and/or directories to be removed
     #[arg(required = true)]
     #[arg(value_hint = ValueHint::AnyPath)]
     files: Vec<PathBuf>,
 
-    /// Ignore non-existent arguments
+    /// Ignore non-existent arguments and never prompt
     #[arg(short, long, default_value_t = false)]
+    #[arg(overrides_with_all = ["prompt_always", "prompt_once"])]
     force: bool,
 
+    /// Prompt before every removal
+    #[arg(short = 'i', default_value_t = false)]
+    #[arg(overrides_with_all = ["force", "prompt_once"])]
+    prompt_always: bool,
+
+    /// Prompt once before removing more than three files or any directory
+    #[arg(short = 'I', default_value_t = false)]
+    #[arg(overrides_with_all = ["force", "prompt_always"])]
+    prompt_once: bool,
+
     /// Allow deletion of `/`
     #[arg(long = "no-preserve-root", default_value_t = true)]
     #[arg(action = ArgAction::SetFalse)]
     preserve_root: bool,
 
+    /// Don't remove directories on other filesystems
+    #[arg(long, default_value_t = false)]
+    one_file_system: bool,
+
+    /// Remove the contents of the directories but keep the directories themselves
+    #[arg(long, default_value_t = false)]
+    contents: bool,
+
+    /// Make your read-only directories writable when that's all that stops their removal
+    #[arg(long, default_value_t = false)]
+    fix_permissions: bool,
+
+    /// Move the files out of the way and finish removing them in a detached process
+    ///
+    /// The files are moved into a hidden `.fuc-staging` directory next to them. Anything an
+    /// interrupted background removal left there is deleted by the next `--background` removal of
+    /// a file from the same directory.
+    #[arg(long, default_value_t = false)]
+    background: bool,
+
+    /// Only remove files last modified longer ago than this, such as `30m`, `12h`, or `7d`
+    ///
+    /// Like the other filters, this makes directories be searched for matching files and only
+    /// removed if they match too and end up empty. Use `--contents` to always keep the
+    /// directories named on the command line.
+    #[arg(long, value_name = "AGE", value_parser = parse_age)]
+    older_than: Option<SystemTime>,
+
+    /// Only remove files last modified more recently than this, such as `30m`, `12h`, or `7d`
+    #[arg(long, value_name = "AGE", value_parser = parse_age)]
+    newer_than: Option<SystemTime>,
+
+    /// Compare ages against when files were last accessed instead of modified
+    #[arg(long, default_value_t = false)]
+    atime: bool,
+
+    /// Only remove files whose name matches one of these globs
+    #[arg(long, value_name = "PATTERN")]
+    name: Vec<String>,
+
+    /// Only remove files at least this big, such as `512`, `64K`, or `1G`
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// Only remove files at most this big, such as `512`, `64K`, or `1G`
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Only remove files of one of these types
+    #[arg(long = "type", value_name = "TYPE", value_enum)]
+    file_type: Vec<FileTypeArg>,
+
+    /// Move files to the trash instead of removing them
+    ///
+    /// Without a value, files that can't be moved to a trash on their own filesystem are reported
+    /// as errors and left in place.
+    #[arg(long, value_name = "WHEN", value_enum)]
+    #[arg(num_args = 0..=1, require_equals = true, default_missing_value = "always")]
+    trash: Option<TrashMode>,
+
+    /// Show a live count of the files removed so far on stderr
+    #[arg(long, default_value_t = false)]
+    progress: bool,
+
+    /// List what would be removed without removing anything
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// Keep removing after a failure and report every failure at the end
+    #[arg(long, default_value_t = false)]
+    continue_on_error: bool,
+
+    /// The maximum number of threads to remove with
+    ///
+    /// Defaults to the number of available CPUs. Use `1` to remove files one at a time.
+    #[arg(short = 'j', long, value_name = "N", env = "FUC_THREADS")]
+    threads: Option<NonZeroUsize>,
+
     #[arg(short, long, short_alias = '?', global = true)]
     #[arg(action = ArgAction::Help, help = "Print help (use `--help` for more detail)")]
     #[arg(long_help = "Print help (use `-h` for a summary)")]
     help: Option<bool>,
 }
 
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum TrashMode {
+    /// Fail for files that can't be moved to a trash on their own filesystem
+    Always,
+    /// Remove files that can't be moved to a trash on their own filesystem
+    OrDelete,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum FileTypeArg {
+    /// Regular files
+    #[value(alias = "f")]
+    File,
+    /// Directories
+    #[value(alias = "d")]
+    Dir,
+    /// Symbolic links
+    #[value(alias = "l")]
+    Symlink,
+    /// Devices, sockets, and FIFOs
+    Other,
+}
+
+/// Parses a number of seconds, minutes, hours, days, or weeks into the time
+/// that long ago.
+fn parse_age(age: &str) -> Result<SystemTime, String> {
+    let unit = match age.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        _ => return Err("expected a unit of s, m, h, d, or w".to_string()),
+    };
+    let count = age[..age.len() - 1]
+        .parse::<u64>()
+        .map_err(|e| e.to_string())?;
+    count
+        .checked_mul(unit)
+        .and_then(|secs| SystemTime::now().checked_sub(Duration::from_secs(secs)))
+        .ok_or_else(|| "age too large".to_string())
+}
+
+/// Parses a number of bytes with an optional binary K, M, G, or T suffix.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let (count, shift) = [('K', 10), ('k', 10), ('M', 20), ('G', 30), ('T', 40)]
+        .into_iter()
+        .find_map(|(suffix, shift)| size.strip_suffix(suffix).map(|count| (count, shift)))
+        .unwrap_or((size, 0));
+    let count = count.parse::<u64>().map_err(|e| e.to_string())?;
+    count
+        .checked_mul(1 << shift)
+        .ok_or_else(|| "size too large".to_string())
+}
+
 #[derive(thiserror::Error, Debug)]
 enum CliError {
     #[error("{0}")]
@@ -79,40 +238,368 @@ fn main() -> error_stack::Result<(), CliError> {
     };
 
     let args = Rmz::parse();
+    let cancel = cancel_on_interrupt();
+    if args.prompt_once && !confirm_once(&args.files) {
+        return Ok(());
+    }
 
-    remove(args).map_err(|e| {
-        let wrapper = CliError::Wrapper(format!("{e}"));
-        match e {
-            Error::Io { error, context } => Report::from(error)
-                .attach_printable(context)
-                .change_context(wrapper),
-            Error::NotFound { file: _ } => {
-                Report::from(wrapper).attach_printable("Use --force to ignore.")
-            }
-            Error::PreserveRoot | Error::Join | Error::BadPath | Error::Internal => {
-                Report::from(wrapper)
+    with_progress(args.progress, |progress| {
+        with_dry_run(args.dry_run, progress, |progress| {
+            remove(args, progress, cancel)
+        })
+    })
+    .map_err(into_report)
+}
+
+fn into_report(e: Error) -> Report<CliError> {
+    let wrapper = CliError::Wrapper(format!("{e}"));
+    match e {
+        Error::Aggregate { errors } => {
+            let mut reports = errors.into_iter().map(into_report);
+            let Some(mut report) = reports.next() else {
+                return Report::from(wrapper);
+            };
+            for next in reports {
+                report.extend_one(next);
             }
-            Error::AlreadyExists { file: _ } => unreachable!(),
+            report.change_context(wrapper)
         }
-    })
+        Error::Io { error, context } => Report::from(error)
+            .attach_printable(context)
+            .change_context(wrapper),
+        Error::NotFound { file: _ } => {
+            Report::from(wrapper).attach_printable("Use --force to ignore.")
+        }
+        Error::PreserveRoot
+        | Error::InvalidPattern { pattern: _ }
+        | Error::Join
+        | Error::BadPath
+        | Error::Cancelled
+        | Error::Internal => Report::from(wrapper),
+        Error::AlreadyExists { file: _ }
+        | Error::SpecialFile { file: _ }
+        | Error::SymlinkLoop { file: _ } => unreachable!(),
+    }
 }
 
 fn remove(
     Rmz {
         files,
         force,
+        prompt_always,
+        prompt_once: _,
         preserve_root,
+        one_file_system,
+        contents,
+        fix_permissions,
+        background,
+        older_than,
+        newer_than,
+        atime,
+        name,
+        min_size,
+        max_size,
+        file_type,
+        trash,
+        progress: _,
+        dry_run,
+        continue_on_error,
+        threads,
         help: _,
     }: Rmz,
+    progress: Arc<dyn Progress>,
+    cancel: CancellationToken,
 ) -> Result<(), Error> {
+    let confirm: Arc<dyn Confirm> = if prompt_always {
+        Arc::new(Prompt {
+            entries: trash.is_none(),
+            asking: Mutex::default(),
+        })
+    } else {
+        Arc::new(())
+    };
+    let timestamp = if atime {
+        Timestamp::Accessed
+    } else {
+        Timestamp::Modified
+    };
+    let mut predicate = name
+        .iter()
+        .try_fold(Predicate::default(), |predicate, pattern| {
+            predicate.name(pattern)
+        })?;
+    if let Some(time) = older_than {
+        predicate = predicate.older_than(timestamp, time);
+    }
+    if let Some(time) = newer_than {
+        predicate = predicate.newer_than(timestamp, time);
+    }
+    if let Some(size) = min_size {
+        predicate = predicate.min_size(size);
+    }
+    if let Some(size) = max_size {
+        predicate = predicate.max_size(size);
+    }
+    for file_type in file_type {
+        predicate = predicate.kind(match file_type {
+            FileTypeArg::File => FileKind::File,
+            FileTypeArg::Dir => FileKind::Directory,
+            FileTypeArg::Symlink => FileKind::Symlink,
+            FileTypeArg::Other => FileKind::Other,
+        });
+    }
+
     RemoveOp::builder()
         .files(files.into_iter())
         .force(force)
         .preserve_root(preserve_root)
+        .one_file_system(one_file_system)
+        .contents_only(contents)
+        .predicate(predicate)
+        .fix_permissions(fix_permissions)
+        .background(background.then(|| -> Arc<dyn Purge> {
+            Arc::new(Detached {
+                one_file_system,
+                fix_permissions,
+                threads,
+            })
+        }))
+        .trash(match trash {
+            None => Trash::Never,
+            Some(TrashMode::Always) => Trash::Always,
+            Some(TrashMode::OrDelete) => Trash::OrDelete,
+        })
+        .confirm(confirm)
+        .progress(progress)
+        .dry_run(dry_run)
+        .continue_on_error(continue_on_error)
+        // Prompts must come one at a time.
+        .threads(if prompt_always {
+            NonZeroUsize::new(1)
+        } else {
+            threads
+        })
+        .cancel(cancel)
         .build()
         .run()
 }
 
+/// Asks the user about every file and directory, the way `rm -i` does.
+#[derive(Debug)]
+struct Prompt {
+    /// Whether directories are emptied one entry at a time rather than being
+    /// moved to the trash as a whole.
+    entries: bool,
+    /// Arguments are confirmed while the directories before them are still
+    /// being emptied, so questions must wait their turn.
+    asking: Mutex<()>,
+}
+
+impl Confirm for Prompt {
+    fn confirm(&self, path: &Path, is_dir: bool) -> bool {
+        self.confirm_entry(path, is_dir)
+    }
+
+    fn confirms_entries(&self) -> bool {
+        self.entries
+    }
+
+    fn confirm_entry(&self, path: &Path, is_dir: bool) -> bool {
+        let _asking = self.asking.lock().unwrap_or_else(PoisonError::into_inner);
+        if is_dir && self.entries {
+            ask(&format!("descend into directory {path:?}?"))
+        } else if is_dir {
+            ask(&format!("remove directory {path:?}?"))
+        } else {
+            ask(&format!("remove file {path:?}?"))
+        }
+    }
+
+    fn confirm_emptied(&self, path: &Path) -> bool {
+        let _asking = self.asking.lock().unwrap_or_else(PoisonError::into_inner);
+        ask(&format!("remove directory {path:?}?"))
+    }
+}
+
+/// Deletes the staged files from another rmz process that keeps running after
+/// this one exits.
+#[derive(Debug)]
+struct Detached {
+    one_file_system: bool,
+    fix_permissions: bool,
+    threads: Option<NonZeroUsize>,
+}
+
+impl Purge for Detached {
+    fn purge(&self, staging: Vec<PathBuf>) -> Result<(), Error> {
+        let io_err = |error| Error::Io {
+            error,
+            context: "Failed to start deleting files in the background".into(),
+        };
+
+        let mut command = Command::new(env::current_exe().map_err(io_err)?);
+        command.args(["--force", "--continue-on-error"]);
+        if self.one_file_system {
+            command.arg("--one-file-system");
+        }
+        if self.fix_permissions {
+            command.arg("--fix-permissions");
+        }
+        if let Some(threads) = self.threads {
+            command.arg(format!("--threads={threads}"));
+        }
+        command
+            .arg("--")
+            .args(staging)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        // A session of its own keeps the helper alive when the terminal goes away.
+        #[cfg(unix)]
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() == -1 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            });
+        }
+        command.spawn().map(drop).map_err(io_err)
+    }
+}
+
+/// Asks once before a removal that's easy to regret, the way `rm -I` does.
+fn confirm_once(files: &[PathBuf]) -> bool {
+    let recursive = files.iter().any(|file| {
+        file.symlink_metadata()
+            .is_ok_and(|metadata| metadata.is_dir())
+    });
+    if files.len() <= 3 && !recursive {
+        return true;
+    }
+
+    let count = files.len();
+    let plural = if count == 1 { "" } else { "s" };
+    if recursive {
+        ask(&format!("remove {count} argument{plural} recursively?"))
+    } else {
+        ask(&format!("remove {count} argument{plural}?"))
+    }
+}
+
+/// Prints the question to stderr and reads a yes or no answer from stdin.
+fn ask(question: &str) -> bool {
+    let _ = write!(io::stderr(), "rmz: {question} ");
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    answer.trim_start().starts_with(['y', 'Y'])
+}
+
+fn with_progress<T>(enabled: bool, f: impl FnOnce(Arc<dyn Progress>) -> T) -> T {
+    if !enabled {
+        return f(Arc::new(()));
+    }
+
+    let counter = Arc::new(ProgressCounter::default());
+    let (done, wait) = mpsc::channel::<()>();
+    let render = thread::spawn({
+        let counter = counter.clone();
+        move || {
+            let print = |end| {
+                let _ = write!(
+                    io::stderr(),
+                    "\r\x1b[KRemoved {} files and {} directories{end}",
+                    counter.files(),
+                    counter.dirs(),
+                );
+            };
+            while wait.recv_timeout(Duration::from_millis(100))
+                == Err(mpsc::RecvTimeoutError::Timeout)
+            {
+                print("");
+            }
+            print("\n");
+        }
+    });
+
+    let result = f(counter);
+    drop(done);
+    let _ = render.join();
+    result
+}
+
+#[derive(Debug)]
+struct DryRun {
+    progress: Arc<dyn Progress>,
+    counter: ProgressCounter,
+}
+
+impl Progress for DryRun {
+    fn file_done(&self, bytes: u64) {
+        self.counter.file_done(bytes);
+        self.progress.file_done(bytes);
+    }
+
+    fn dir_done(&self) {
+        self.counter.dir_done();
+        self.progress.dir_done();
+    }
+
+    fn planned(&self, path: &Path) {
+        let _ = writeln!(io::stdout(), "{}", path.display());
+    }
+}
+
+fn with_dry_run<T>(
+    enabled: bool,
+    progress: Arc<dyn Progress>,
+    f: impl FnOnce(Arc<dyn Progress>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    if !enabled {
+        return f(progress);
+    }
+
+    let dry_run = Arc::new(DryRun {
+        progress,
+        counter: ProgressCounter::default(),
+    });
+    let result = f(dry_run.clone())?;
+    let _ = writeln!(
+        io::stdout(),
+        "Would remove {} files and {} directories",
+        dry_run.counter.files(),
+        dry_run.counter.dirs(),
+    );
+    Ok(result)
+}
+
+/// Turns the first Ctrl-C into a clean stop between directory entries. The
+/// handler resets itself, so a second Ctrl-C kills the process as usual.
+fn cancel_on_interrupt() -> CancellationToken {
+    static TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+    extern "C" fn handle(_: libc::c_int) {
+        if let Some(token) = TOKEN.get() {
+            token.cancel();
+        }
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+
+    let token = TOKEN.get_or_init(CancellationToken::default).clone();
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            handle as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    token
+}
+
 #[cfg(test)]
 mod cli_tests {
     use clap::CommandFactory;